@@ -0,0 +1,53 @@
+// keys.rs
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use rand::rngs::OsRng;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+// Line the server starts a key challenge with
+const CHALLENGE_PREFIX: &str = "🔏 Challenge: ";
+
+/// Creates a key for signing in, saves its secret half to `path` and returns the public
+/// half to register the account with. An existing file is never overwritten.
+pub fn generate(path: &Path) -> Result<String, String> {
+    let key = SigningKey::generate(&mut OsRng);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Can't create {}: {}", path.display(), e))?;
+    writeln!(file, "{}", BASE64.encode(key.to_bytes()))
+        .map_err(|e| format!("Can't write {}: {}", path.display(), e))?;
+    Ok(BASE64.encode(key.verifying_key().to_bytes()))
+}
+
+/// Reads a key saved by `generate`.
+pub fn load(path: &Path) -> Result<SigningKey, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+    let bytes: [u8; 32] = BASE64
+        .decode(text.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{} doesn't hold a key made with --gen-key", path.display()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// The `signature` answer to a key challenge in a message from the server, if it has one.
+pub fn answer(key: &SigningKey, message: &str) -> Option<String> {
+    let challenge = message
+        .lines()
+        .find_map(|line| line.strip_prefix(CHALLENGE_PREFIX))?
+        .trim();
+    let signature = key.sign(challenge.as_bytes());
+    Some(format!("signature {}", BASE64.encode(signature.to_bytes())))
+}
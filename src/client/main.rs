@@ -1,21 +1,83 @@
+mod keys;
+mod replay;
+
+use clap::Parser;
+use ed25519_dalek::SigningKey;
 use futures::{SinkExt, StreamExt};
+use std::path::PathBuf;
 use tokio::io::{self, AsyncBufReadExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio_tungstenite::MaybeTlsStream;
 use tokio_tungstenite::WebSocketStream;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio_tungstenite::{connect_async, connect_async_tls_with_config, Connector};
 
 type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 type WsSink = futures::stream::SplitSink<WsStream, Message>;
 
+#[derive(Debug, Parser)]
+#[command(name = "client", about = "Text client for the Tic-Tac-Toe server")]
+struct Args {
+    /// Server URL, `ws://` or `wss://`
+    #[arg(default_value = "ws://127.0.0.1:8080")]
+    url: String,
+    /// PEM certificate of a CA to trust for `wss://`, e.g. for a self-signed server
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+    /// Step through a saved replay (JSON export or `X:5 O:1 ...` notation) instead of connecting
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Key to answer the server's challenge with after typing `key <name>`
+    #[arg(long, value_name = "FILE")]
+    key: Option<PathBuf>,
+    /// Create a new key for signing in, print its public half and exit
+    #[arg(long, value_name = "FILE")]
+    gen_key: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
+
+    if let Some(path) = &args.replay {
+        if let Err(e) = replay::run(path) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.gen_key {
+        match keys::generate(path) {
+            Ok(public_key) => println!(
+                "🔑 Saved a new key to {}. Its public key is:\n{}\nCreate an account with `register <name> key {}`, then connect with `--key {}` and sign in with `key <name>`.",
+                path.display(),
+                public_key,
+                public_key,
+                path.display()
+            ),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let key = match args.key.as_deref().map(keys::load).transpose() {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Spawn task to listen for Ctrl+C
     let shutdown_signal = listen_for_shutdown_signal();
 
     // Connect to the WebSocket server
-    let (ws_stream, _) = connect_async("ws://127.0.0.1:8080")
+    let ws_stream = connect(&args)
         .await
         .expect("Failed to connect to WebSocket server");
     let (ws_write, ws_read) = ws_stream.split();
@@ -23,9 +85,9 @@ async fn main() {
     // Set up the channel and tasks
     let (tx, rx) = mpsc::unbounded_channel::<String>();
 
-    let input_task = spawn_input_task(tx);
+    let input_task = spawn_input_task(tx.clone());
     let send_task = spawn_send_task(rx, ws_write);
-    let receive_task = spawn_receive_task(ws_read);
+    let receive_task = spawn_receive_task(ws_read, key, tx);
 
     // Wait for tasks to complete or shutdown signal
     tokio::select! {
@@ -46,6 +108,26 @@ async fn main() {
     println!("Client terminated.");
 }
 
+async fn connect(args: &Args) -> Result<WsStream, Box<dyn std::error::Error>> {
+    let ca_cert = match &args.ca_cert {
+        Some(path) => path,
+        None => {
+            let (ws_stream, _) = connect_async(args.url.as_str()).await?;
+            return Ok(ws_stream);
+        }
+    };
+
+    // Trust the given CA in addition to the system roots
+    let pem = std::fs::read(ca_cert)?;
+    let connector = native_tls::TlsConnector::builder()
+        .add_root_certificate(native_tls::Certificate::from_pem(&pem)?)
+        .build()?;
+    let connector = Connector::NativeTls(connector);
+    let (ws_stream, _) =
+        connect_async_tls_with_config(args.url.as_str(), None, false, Some(connector)).await?;
+    Ok(ws_stream)
+}
+
 // Task to listen for shutdown signal (Ctrl+C)
 fn listen_for_shutdown_signal() -> tokio::task::JoinHandle<()> {
     tokio::spawn(async {
@@ -64,9 +146,14 @@ fn spawn_input_task(tx: mpsc::UnboundedSender<String>) -> tokio::task::JoinHandl
 
         loop {
             let mut input = String::new();
-            if reader.read_line(&mut input).await.is_err() {
-                eprintln!("Failed to read user input");
-                break;
+            match reader.read_line(&mut input).await {
+                // End of input
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(_) => {
+                    eprintln!("Failed to read user input");
+                    break;
+                }
             }
 
             if tx.send(input.trim().to_string()).is_err() {
@@ -93,14 +180,27 @@ fn spawn_send_task(
     })
 }
 
-// Task to handle receiving messages from the server
+// Task to handle receiving messages from the server. Key challenges are answered
+// straight away when a key was given.
 fn spawn_receive_task(
     mut ws_read: futures::stream::SplitStream<WsStream>,
+    key: Option<SigningKey>,
+    tx: mpsc::UnboundedSender<String>,
 ) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
         while let Some(msg) = ws_read.next().await {
             match msg {
-                Ok(Message::Text(text)) => println!("\n{}", text),
+                Ok(Message::Text(text)) => {
+                    println!("\n{}", text);
+                    if let Some(answer) = key.as_ref().and_then(|key| keys::answer(key, &text)) {
+                        println!("🔏 Signing the challenge with your key...");
+                        let _ = tx.send(answer);
+                    }
+                }
+                Ok(Message::Close(Some(frame))) if !frame.reason.is_empty() => {
+                    println!("Connection closed by server: {}", frame.reason);
+                    break;
+                }
                 Ok(Message::Close(_)) | Err(_) => {
                     println!("Connection closed by server.");
                     break;
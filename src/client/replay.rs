@@ -0,0 +1,225 @@
+// replay.rs
+
+use serde::Deserialize;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+// Directions checked for k-in-a-row: right, down, down-right, down-left
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+#[derive(Debug, Deserialize)]
+struct ReplayPlayer {
+    name: String,
+    symbol: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplayMove {
+    #[serde(default)]
+    player: String,
+    symbol: String,
+    // 0-based board index
+    cell: usize,
+}
+
+/// A replay as exported by the server's `replay <game-id> json` command.
+#[derive(Debug, Deserialize)]
+struct Replay {
+    #[serde(default)]
+    game_id: String,
+    rows: usize,
+    cols: usize,
+    win_length: usize,
+    #[serde(default)]
+    players: Vec<ReplayPlayer>,
+    moves: Vec<ReplayMove>,
+    #[serde(default)]
+    winner: Option<String>,
+    // The loser ran out of time, so the board alone doesn't show the result
+    #[serde(default)]
+    won_on_time: bool,
+}
+
+impl Replay {
+    // Compact notation such as `X:5 O:1 X:9`, which only names cells on the classic board
+    fn from_notation(text: &str) -> Result<Self, String> {
+        let moves = text
+            .split_whitespace()
+            .map(|token| {
+                let (symbol, cell) = token
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected `SYMBOL:CELL`, got `{}`", token))?;
+                let symbol = symbol.to_uppercase();
+                match (symbol.as_str(), cell.parse::<usize>()) {
+                    ("X" | "O", Ok(cell @ 1..=9)) => Ok(ReplayMove {
+                        player: String::new(),
+                        symbol,
+                        cell: cell - 1,
+                    }),
+                    _ => Err(format!("`{}` isn't a move on the classic board", token)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Replay {
+            game_id: String::new(),
+            rows: 3,
+            cols: 3,
+            win_length: 3,
+            players: Vec::new(),
+            moves,
+            winner: None,
+            won_on_time: false,
+        })
+    }
+
+    fn cell_name(&self, index: usize) -> String {
+        if self.rows * self.cols <= 9 {
+            (index + 1).to_string()
+        } else {
+            format!(
+                "{}{}",
+                (b'a' + (index % self.cols) as u8) as char,
+                index / self.cols + 1
+            )
+        }
+    }
+
+    fn player_name(&self, symbol: &str) -> Option<&str> {
+        self.players
+            .iter()
+            .find(|player| player.symbol == symbol)
+            .map(|player| player.name.as_str())
+    }
+
+    fn render(&self, board: &[Option<&str>]) -> String {
+        let mut text = String::new();
+        if self.rows * self.cols > 9 {
+            text.push_str("   ");
+            for col in 0..self.cols {
+                text.push_str(&format!(" {} ", (b'a' + col as u8) as char));
+            }
+            text.push('\n');
+        }
+        for row in 0..self.rows {
+            if self.rows * self.cols > 9 {
+                text.push_str(&format!("{:>2} ", row + 1));
+            }
+            for col in 0..self.cols {
+                let index = row * self.cols + col;
+                match board[index] {
+                    Some(symbol) => text.push_str(&format!(" {} ", symbol)),
+                    None if self.rows * self.cols <= 9 => {
+                        text.push_str(&format!(" {} ", index + 1))
+                    }
+                    None => text.push_str(" · "),
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn winner<'a>(&self, board: &[Option<&'a str>]) -> Option<&'a str> {
+        let at = |row: isize, col: isize| {
+            if row < 0 || col < 0 || row >= self.rows as isize || col >= self.cols as isize {
+                None
+            } else {
+                board[row as usize * self.cols + col as usize]
+            }
+        };
+        for row in 0..self.rows as isize {
+            for col in 0..self.cols as isize {
+                let symbol = match at(row, col) {
+                    Some(symbol) => symbol,
+                    None => continue,
+                };
+                for (dr, dc) in DIRECTIONS {
+                    if (1..self.win_length as isize)
+                        .all(|step| at(row + dr * step, col + dc * step) == Some(symbol))
+                    {
+                        return Some(symbol);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+fn load(path: &Path) -> Result<Replay, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if text.trim_start().starts_with('{') {
+        serde_json::from_str(&text).map_err(|e| format!("Invalid replay file: {}", e))
+    } else {
+        Replay::from_notation(&text)
+    }
+}
+
+/// Steps through a saved replay, one move each time Enter is pressed, until the game
+/// ends or the user types `q`.
+pub fn run(path: &Path) -> Result<(), String> {
+    let replay = load(path)?;
+    let cells = replay.rows * replay.cols;
+    if replay.moves.iter().any(|record| record.cell >= cells) {
+        return Err("The replay has a move outside its board".to_string());
+    }
+
+    let mut board: Vec<Option<&str>> = vec![None; cells];
+    println!(
+        "🎬 Replay{} | {}x{}, {} in a row | {} moves",
+        if replay.game_id.is_empty() {
+            String::new()
+        } else {
+            format!(" of game {}", replay.game_id)
+        },
+        replay.rows,
+        replay.cols,
+        replay.win_length,
+        replay.moves.len()
+    );
+    println!("\n{}", replay.render(&board));
+    println!("Press Enter for the next move, or type `q` to quit.");
+
+    let mut lines = io::stdin().lock().lines();
+    for (number, record) in replay.moves.iter().enumerate() {
+        print!("> ");
+        let _ = io::stdout().flush();
+        match lines.next() {
+            Some(Ok(line)) if line.trim().eq_ignore_ascii_case("q") => return Ok(()),
+            Some(Ok(_)) => {}
+            _ => return Ok(()),
+        }
+
+        board[record.cell] = Some(record.symbol.as_str());
+        let player = match record.player.as_str() {
+            "" => record.symbol.clone(),
+            name => format!("{} ({})", name, record.symbol),
+        };
+        println!(
+            "🎬 Move {}/{}: {} played {}\n\n{}",
+            number + 1,
+            replay.moves.len(),
+            player,
+            replay.cell_name(record.cell),
+            replay.render(&board)
+        );
+    }
+
+    if let (true, Some(symbol)) = (replay.won_on_time, &replay.winner) {
+        match replay.player_name(symbol) {
+            Some(name) => println!("🏁 {} ({}) won on time", name, symbol),
+            None => println!("🏁 {} won on time", symbol),
+        }
+        return Ok(());
+    }
+    match replay.winner(&board) {
+        Some(symbol) => match replay.player_name(symbol) {
+            Some(name) => println!("🏁 {} ({}) won", name, symbol),
+            None => println!("🏁 {} won", symbol),
+        },
+        None if board.iter().all(|cell| cell.is_some()) => println!("🏁 Draw"),
+        None => println!("🏁 End of replay, the game was left unfinished"),
+    }
+    Ok(())
+}
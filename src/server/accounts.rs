@@ -0,0 +1,440 @@
+// accounts.rs
+
+use crate::names::MAX_NAME;
+use crate::protocol::{ErrorCode, GameMessage};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use log::error;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+const MIN_PASSWORD: usize = 8;
+const MAX_PASSWORD: usize = 128;
+
+/// A registered player. Only an argon2 hash of the password is kept; an account may
+/// instead, or as well, sign in with an ed25519 key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    // Base64 of the 32-byte public key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    InvalidName(String),
+    WeakPassword,
+    InvalidKey,
+    MissingCredentials,
+    // Registering a name that already has an account
+    NameTaken(String),
+    // Playing as a guest under an account's name; the error doesn't say so, as that
+    // would tell anyone which names have accounts
+    Unavailable,
+    WrongPassword,
+    WrongSignature,
+    NoChallenge,
+    GuestsDisabled,
+    // Someone connected is already using the name
+    InUse(String),
+    TooManyAttempts,
+    Storage,
+}
+
+impl AuthError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AuthError::InvalidName(_) => ErrorCode::InvalidName,
+            AuthError::WeakPassword | AuthError::InvalidKey | AuthError::MissingCredentials => {
+                ErrorCode::InvalidCredentials
+            }
+            AuthError::NameTaken(_) | AuthError::Unavailable => ErrorCode::NameTaken,
+            AuthError::WrongPassword
+            | AuthError::WrongSignature
+            | AuthError::NoChallenge
+            | AuthError::TooManyAttempts
+            | AuthError::Storage => ErrorCode::AuthFailed,
+            AuthError::GuestsDisabled => ErrorCode::GuestsNotAllowed,
+            AuthError::InUse(_) => ErrorCode::NameInUse,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidName(reason) => write!(f, "{}", reason),
+            AuthError::WeakPassword => write!(
+                f,
+                "Passwords must be {} to {} characters long",
+                MIN_PASSWORD, MAX_PASSWORD
+            ),
+            AuthError::InvalidKey => write!(f, "Public keys must be 32 bytes in base64"),
+            AuthError::MissingCredentials => {
+                write!(f, "An account needs a password or a public key")
+            }
+            AuthError::NameTaken(name) => write!(f, "There is already an account named {}", name),
+            AuthError::Unavailable => write!(
+                f,
+                "That name isn't available, sign in if it's yours or pick another name"
+            ),
+            AuthError::WrongPassword => write!(f, "Wrong name or password"),
+            AuthError::WrongSignature => write!(f, "The signature doesn't match the account's key"),
+            AuthError::NoChallenge => {
+                write!(f, "Ask for a challenge with `key <name>` before signing it")
+            }
+            AuthError::GuestsDisabled => write!(
+                f,
+                "Guests can't play here, sign in with `login <name> <password>` or create an account with `register <name> <password>`"
+            ),
+            AuthError::InUse(name) => write!(f, "{} is already connected", name),
+            AuthError::TooManyAttempts => write!(f, "Too many failed sign-in attempts"),
+            AuthError::Storage => write!(f, "The account couldn't be saved, please try again"),
+        }
+    }
+}
+
+impl From<AuthError> for GameMessage {
+    fn from(error: AuthError) -> Self {
+        GameMessage::Error(error.code(), error.to_string())
+    }
+}
+
+/// Registered accounts, kept in a local JSON file that is rewritten whenever an account
+/// is added.
+#[derive(Debug)]
+pub struct Accounts {
+    // `None` keeps accounts for as long as the server runs
+    path: Option<PathBuf>,
+    // Lowercased name to account, so names are unique regardless of case
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+impl Accounts {
+    pub fn in_memory() -> Self {
+        // Made up front, so the first sign-in to a missing account isn't the slow one
+        dummy_hash();
+        Accounts {
+            path: None,
+            accounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Loads the accounts file, starting empty if it doesn't exist yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let accounts: Vec<Account> = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        dummy_hash();
+        Ok(Accounts {
+            path: Some(path.to_path_buf()),
+            accounts: Mutex::new(
+                accounts
+                    .into_iter()
+                    .map(|account| (key(&account.name), account))
+                    .collect(),
+            ),
+        })
+    }
+
+    fn accounts(&self) -> MutexGuard<'_, HashMap<String, Account>> {
+        self.accounts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts().is_empty()
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts().contains_key(&key(name))
+    }
+
+    /// Creates an account signed into with `password`, `public_key` or either. Hashing is
+    /// deliberately slow, so call this off the async runtime.
+    pub fn register(
+        &self,
+        name: &str,
+        password: Option<&str>,
+        public_key: Option<&str>,
+    ) -> Result<Account, AuthError> {
+        let name = check_name(name)?;
+        if password.is_none() && public_key.is_none() {
+            return Err(AuthError::MissingCredentials);
+        }
+        if self.is_registered(&name) {
+            return Err(AuthError::NameTaken(name));
+        }
+        let public_key = public_key
+            .map(|public_key| {
+                let public_key = public_key.trim();
+                parse_public_key(public_key).map(|_| public_key.to_string())
+            })
+            .transpose()?;
+        let password_hash = password.map(hash_password).transpose()?;
+
+        let account = Account {
+            name: name.clone(),
+            password_hash,
+            public_key,
+            created_at: Utc::now(),
+        };
+        let mut accounts = self.accounts();
+        // Someone may have taken the name while the password was being hashed
+        if accounts.contains_key(&key(&name)) {
+            return Err(AuthError::NameTaken(name));
+        }
+        accounts.insert(key(&name), account.clone());
+        if let Err(e) = self.save(&accounts) {
+            error!("Failed to save the accounts file: {}", e);
+            accounts.remove(&key(&name));
+            return Err(AuthError::Storage);
+        }
+        Ok(account)
+    }
+
+    /// Checks a password and returns the account's name as registered. Slow, like
+    /// `register`.
+    pub fn verify_password(&self, name: &str, password: &str) -> Result<String, AuthError> {
+        let account = self.accounts().get(&key(name.trim())).cloned();
+        let found = match account {
+            Some(Account {
+                name,
+                password_hash: Some(hash),
+                ..
+            }) => Some((name, hash)),
+            _ => None,
+        };
+        // Without a hash to check, a dummy one is checked anyway, so how long the answer
+        // takes doesn't tell whether the name has an account
+        let hash = found
+            .as_ref()
+            .map_or(dummy_hash(), |(_, hash)| hash.as_str());
+        let hash = PasswordHash::new(hash).map_err(|_| AuthError::WrongPassword)?;
+        let verified = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        match found {
+            Some((name, _)) if verified => Ok(name),
+            _ => Err(AuthError::WrongPassword),
+        }
+    }
+
+    /// Checks a base64 signature of `challenge` made with the account's key.
+    pub fn verify_signature(
+        &self,
+        name: &str,
+        challenge: &str,
+        signature: &str,
+    ) -> Result<String, AuthError> {
+        let account = self.accounts().get(&key(name)).cloned();
+        let (name, public_key) = match account {
+            Some(Account {
+                name,
+                public_key: Some(public_key),
+                ..
+            }) => (name, public_key),
+            // Fails like a wrong signature, so the answer doesn't tell whether the name
+            // has an account, or one with a key
+            _ => return Err(AuthError::WrongSignature),
+        };
+        let public_key = parse_public_key(&public_key)?;
+        let signature = BASE64
+            .decode(signature.trim())
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(AuthError::WrongSignature)?;
+        public_key
+            .verify(challenge.as_bytes(), &signature)
+            .map_err(|_| AuthError::WrongSignature)?;
+        Ok(name)
+    }
+
+    // Writes a new file and renames it over the old one, so a crash never leaves half a file
+    fn save(&self, accounts: &HashMap<String, Account>) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut list: Vec<&Account> = accounts.values().collect();
+        list.sort_by_key(|account| account.created_at);
+        let text = serde_json::to_string_pretty(&list)?;
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, text)?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+/// Account names are kept short and to letters, digits, `-`, `_` and `.`, so they can be
+/// typed in the text protocol's `login <name> <password>`.
+pub fn check_name(name: &str) -> Result<String, AuthError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME {
+        return Err(AuthError::InvalidName(format!(
+            "Account names must be 1 to {} characters long",
+            MAX_NAME
+        )));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(AuthError::InvalidName(
+            "Account names may only use letters, digits, `-`, `_` and `.`".to_string(),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+// Hash of a password no account has, made with the same parameters as real ones
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password("not the password of any account").unwrap_or_default())
+}
+
+fn hash_password(password: &str) -> Result<String, AuthError> {
+    let length = password.chars().count();
+    if !(MIN_PASSWORD..=MAX_PASSWORD).contains(&length) {
+        return Err(AuthError::WeakPassword);
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            error!("Failed to hash a password: {}", e);
+            AuthError::Storage
+        })
+}
+
+fn parse_public_key(public_key: &str) -> Result<VerifyingKey, AuthError> {
+    let bytes: [u8; 32] = BASE64
+        .decode(public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(AuthError::InvalidKey)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| AuthError::InvalidKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn public_key() -> String {
+        BASE64.encode(signing_key().verifying_key().as_bytes())
+    }
+
+    #[test]
+    fn names_are_unique_regardless_of_case() {
+        let accounts = Accounts::in_memory();
+        accounts
+            .register("Alice", Some("correct horse"), None)
+            .unwrap();
+        assert!(accounts.is_registered("alice"));
+        assert!(matches!(
+            accounts.register("ALICE", Some("another one"), None),
+            Err(AuthError::NameTaken(_))
+        ));
+    }
+
+    #[test]
+    fn registration_needs_a_usable_name_and_credentials() {
+        let accounts = Accounts::in_memory();
+        assert!(matches!(
+            accounts.register("al ice", Some("correct horse"), None),
+            Err(AuthError::InvalidName(_))
+        ));
+        assert!(matches!(
+            accounts.register("alice", None, None),
+            Err(AuthError::MissingCredentials)
+        ));
+        assert!(matches!(
+            accounts.register("alice", Some("short"), None),
+            Err(AuthError::WeakPassword)
+        ));
+        assert!(matches!(
+            accounts.register("alice", None, Some("not a key")),
+            Err(AuthError::InvalidKey)
+        ));
+        assert!(accounts.is_empty());
+    }
+
+    #[test]
+    fn passwords_sign_in_under_the_registered_name() {
+        let accounts = Accounts::in_memory();
+        accounts
+            .register("Alice", Some("correct horse"), None)
+            .unwrap();
+        assert_eq!(
+            accounts
+                .verify_password(" alice ", "correct horse")
+                .unwrap(),
+            "Alice"
+        );
+        assert!(matches!(
+            accounts.verify_password("alice", "wrong horse"),
+            Err(AuthError::WrongPassword)
+        ));
+        assert!(matches!(
+            accounts.verify_password("nobody", "correct horse"),
+            Err(AuthError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn keys_sign_in_with_a_signed_challenge() {
+        let accounts = Accounts::in_memory();
+        accounts
+            .register("alice", None, Some(&public_key()))
+            .unwrap();
+        let signature = BASE64.encode(signing_key().sign(b"challenge").to_bytes());
+        assert_eq!(
+            accounts
+                .verify_signature("alice", "challenge", &signature)
+                .unwrap(),
+            "alice"
+        );
+        assert!(matches!(
+            accounts.verify_signature("alice", "another challenge", &signature),
+            Err(AuthError::WrongSignature)
+        ));
+        assert!(matches!(
+            accounts.verify_password("alice", "anything at all"),
+            Err(AuthError::WrongPassword)
+        ));
+        assert!(matches!(
+            accounts.verify_signature("nobody", "challenge", &signature),
+            Err(AuthError::WrongSignature)
+        ));
+    }
+}
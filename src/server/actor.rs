@@ -0,0 +1,977 @@
+// actor.rs
+
+use crate::board::BoardConfig;
+use crate::bot;
+use crate::clock::TimeControl;
+use crate::game::{Game, GameError, GameStatus};
+use crate::metrics::Outcome;
+use crate::outbox::Outbox;
+use crate::player::{Player, PlayerSymbol};
+use crate::protocol::{GameMessage, GameSnapshot, Protocol};
+use crate::rating::Ending;
+use crate::render::{JsonRenderer, PlainRenderer, Renderer};
+use crate::replay;
+use crate::server::GameServer;
+use crate::storage::GameEvent;
+use log::{error, info, warn};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, Duration};
+
+// How long a dropped player's seat is held for them to resume
+pub const RECONNECT_GRACE_SECS: u64 = 60;
+
+type Reply<T> = oneshot::Sender<T>;
+
+/// Requests a game's task handles one at a time, in the order they arrive.
+#[derive(Debug)]
+pub enum GameCommand {
+    Join {
+        player: Player,
+        reply: Reply<Result<(), GameError>>,
+    },
+    Watch {
+        spectator: Player,
+        reply: Reply<()>,
+    },
+    // Only the player whose name the seat was taken under may resume it
+    Resume {
+        token: String,
+        name: String,
+        outbox: Outbox,
+        protocol: Protocol,
+        reply: Reply<Result<Player, GameError>>,
+    },
+    Move {
+        player_id: String,
+        position: usize,
+    },
+    Say {
+        player_id: String,
+        text: String,
+    },
+    Restart {
+        player_id: String,
+    },
+    Takeback {
+        player_id: String,
+    },
+    AnswerTakeback {
+        player_id: String,
+        accept: bool,
+    },
+    ExpireTakeback {
+        request_id: String,
+    },
+    // The player to move may have run out of time; `turn` tells a stale check apart
+    CheckClock {
+        turn: u64,
+    },
+    Status {
+        participant_id: String,
+    },
+    // Whose turn it is, or `None` while the game isn't in progress
+    CurrentTurn {
+        reply: Reply<Option<String>>,
+    },
+    // Gives up a seat, or stops watching, straight away
+    Leave {
+        participant_id: String,
+    },
+    // Holds the seat for a reconnect unless a newer connection already has it
+    ConnectionLost {
+        player_id: String,
+        connection_id: String,
+    },
+    ExpireSeat {
+        player_id: String,
+        connection_id: String,
+    },
+    // The whole game as the admin API shows it
+    Snapshot {
+        reply: Reply<GameSnapshot>,
+    },
+    // Closes the game for everyone, e.g. when an administrator removes a stuck game
+    Close {
+        reason: Option<String>,
+        reply: Reply<()>,
+    },
+    // Warns everyone in the game that the server stops in `seconds`
+    ShuttingDown {
+        seconds: u64,
+    },
+    // Stops the game as the server shuts down, leaving its records so it can be restored.
+    // Answers whether a round was cut short.
+    Abort {
+        reply: Reply<bool>,
+    },
+}
+
+/// Reaches a running game. Cheap to clone; every connection in the game holds one.
+#[derive(Debug, Clone)]
+pub struct GameHandle {
+    commands: mpsc::UnboundedSender<GameCommand>,
+    // The board and time control are fixed when the game is created
+    pub config: BoardConfig,
+    pub time_control: TimeControl,
+}
+
+impl GameHandle {
+    /// Queues a command for the game. Returns `false` once the game has closed.
+    pub fn send(&self, command: GameCommand) -> bool {
+        self.commands.send(command).is_ok()
+    }
+
+    /// Sends a command carrying a reply channel and waits for the answer,
+    /// or `None` if the game closed before answering.
+    pub async fn request<T>(&self, command: impl FnOnce(Reply<T>) -> GameCommand) -> Option<T> {
+        let (reply, answer) = oneshot::channel();
+        if !self.send(command(reply)) {
+            return None;
+        }
+        answer.await.ok()
+    }
+
+    /// Resolves once the game has closed.
+    pub async fn closed(&self) {
+        self.commands.closed().await
+    }
+}
+
+/// Starts the task that owns `game` from now on and returns the handle for reaching it.
+pub fn spawn(game: Game, server: Arc<GameServer>) -> GameHandle {
+    let (commands, mut inbox) = mpsc::unbounded_channel();
+    let handle = GameHandle {
+        commands: commands.clone(),
+        config: game.config,
+        time_control: game.clock.control,
+    };
+    let mut actor = GameActor {
+        game,
+        server,
+        commands,
+        closed: false,
+    };
+    // Seats restored after a restart wait for their players like dropped connections do
+    for player in actor.game.players.iter() {
+        if !player.is_bot() && !player.is_connected() {
+            actor.hold_seat(&player.get_id(), &player.get_connection_id());
+        }
+    }
+    tokio::spawn(async move {
+        while let Some(command) = inbox.recv().await {
+            actor.handle(command);
+            if actor.closed {
+                break;
+            }
+        }
+    });
+    handle
+}
+
+/// Gives a player the free symbol and starts the game once both seats are taken.
+pub fn seat_player(game: &mut Game, mut player: Player) {
+    let player_name = player.get_name();
+
+    // Determine which symbol the player will get
+    if let Some(game_player) = game.get_players().first() {
+        if game_player.get_symbol() == PlayerSymbol::O {
+            player.set_symbol(PlayerSymbol::X);
+        } else {
+            player.set_symbol(PlayerSymbol::O);
+        }
+    } else {
+        player.set_symbol(PlayerSymbol::O); // First player always gets 'O'
+    }
+
+    // Add the player to the game
+    game.add_player(player);
+    game.broadcast_to_players(GameMessage::PlayerJoined(player_name));
+
+    if game.get_players().len() == 2 {
+        game.set_status(GameStatus::InProgress);
+        let game_state = game.view();
+        game.broadcast_to_players(GameMessage::GameState(game_state));
+    }
+}
+
+struct GameActor {
+    game: Game,
+    server: Arc<GameServer>,
+    // Lets timers started by the task send commands back to it
+    commands: mpsc::UnboundedSender<GameCommand>,
+    closed: bool,
+}
+
+impl GameActor {
+    fn handle(&mut self, command: GameCommand) {
+        match command {
+            GameCommand::Join { player, reply } => {
+                let _ = reply.send(self.join(player));
+            }
+            GameCommand::Watch { spectator, reply } => {
+                self.watch(spectator);
+                let _ = reply.send(());
+            }
+            GameCommand::Resume {
+                token,
+                name,
+                outbox,
+                protocol,
+                reply,
+            } => {
+                let _ = reply.send(self.resume(&token, &name, outbox, protocol));
+            }
+            GameCommand::Move {
+                player_id,
+                position,
+            } => self.make_move(&player_id, position),
+            GameCommand::Say { player_id, text } => self.say(&player_id, &text),
+            GameCommand::Restart { player_id } => self.restart(&player_id),
+            GameCommand::Takeback { player_id } => self.request_takeback(&player_id),
+            GameCommand::AnswerTakeback { player_id, accept } => {
+                self.answer_takeback(&player_id, accept)
+            }
+            GameCommand::ExpireTakeback { request_id } => self.expire_takeback(&request_id),
+            GameCommand::CheckClock { turn } => {
+                if self.game.clock.turn() == turn {
+                    self.flag();
+                }
+            }
+            GameCommand::Status { participant_id } => {
+                self.reply(&participant_id, GameMessage::GameStatus(self.game.view()))
+            }
+            GameCommand::CurrentTurn { reply } => {
+                let player = match self.game.get_status() {
+                    GameStatus::InProgress => self.game.get_current_turn_player(),
+                    _ => None,
+                };
+                let _ = reply.send(player);
+            }
+            GameCommand::Leave { participant_id } => self.leave(&participant_id),
+            GameCommand::ConnectionLost {
+                player_id,
+                connection_id,
+            } => self.connection_lost(&player_id, &connection_id),
+            GameCommand::ExpireSeat {
+                player_id,
+                connection_id,
+            } => self.expire_seat(&player_id, &connection_id),
+            GameCommand::Snapshot { reply } => {
+                let _ = reply.send(JsonRenderer::snapshot(&self.game));
+            }
+            GameCommand::Close { reason, reply } => {
+                self.close(reason);
+                let _ = reply.send(());
+            }
+            GameCommand::ShuttingDown { seconds } => self
+                .game
+                .broadcast_to_players(GameMessage::ShuttingDown(seconds)),
+            GameCommand::Abort { reply } => {
+                let _ = reply.send(self.abort());
+            }
+        }
+        if !self.closed {
+            self.sync_clock();
+        }
+    }
+
+    // Answers one player or spectator directly
+    fn reply(&self, participant_id: &str, message: GameMessage) {
+        let participant = self
+            .game
+            .players
+            .iter()
+            .chain(self.game.spectators.iter())
+            .find(|p| p.get_id() == participant_id);
+        if let Some(participant) = participant {
+            participant.reply(&message);
+        }
+    }
+
+    // Keeps the game's lobby row current after anything it shows has changed
+    fn relist(&self) {
+        self.server.update_listing(&self.game);
+    }
+
+    fn join(&mut self, player: Player) -> Result<(), GameError> {
+        let game_id = self.game.get_id();
+        if self.game.get_status() == GameStatus::Finished {
+            return Err(GameError::AlreadyFinished(game_id));
+        }
+        if self.game.get_status() != GameStatus::WaitingForPlayers
+            || self.game.get_players().len() >= 2
+        {
+            return Err(GameError::Full(game_id));
+        }
+
+        seat_player(&mut self.game, player);
+        // The symbol is only settled once seated
+        if let Some(player) = self.game.players.last() {
+            self.record(GameEvent::seated(player));
+        }
+        self.relist();
+        Ok(())
+    }
+
+    fn watch(&mut self, spectator: Player) {
+        let spectator_name = spectator.get_name();
+        spectator.notify(&GameMessage::Watching(self.game.get_id()));
+        spectator.notify(&GameMessage::GameState(self.game.view()));
+        if !self.game.chat.is_empty() {
+            spectator.notify(&GameMessage::ChatHistory(self.game.chat_history()));
+        }
+        self.game.add_spectator(spectator);
+        let count = self.game.spectator_count();
+        self.game
+            .broadcast_to_players(GameMessage::SpectatorJoined(spectator_name, count));
+        self.relist();
+    }
+
+    // Gives a held seat back to a reconnecting player and re-sends the board to it
+    fn resume(
+        &mut self,
+        token: &str,
+        name: &str,
+        outbox: Outbox,
+        protocol: Protocol,
+    ) -> Result<Player, GameError> {
+        let game_id = self.game.get_id();
+        let player = self
+            .game
+            .players
+            .iter_mut()
+            .find(|p| {
+                !p.is_bot()
+                    && p.get_resume_token() == token
+                    && p.get_name().to_lowercase() == name.to_lowercase()
+            })
+            .ok_or(GameError::InvalidResumeToken)?;
+
+        if let Some(previous) = player.reconnect(outbox, protocol) {
+            // Close a stale connection that hasn't noticed it was replaced
+            previous.close();
+        }
+        let player = player.clone();
+
+        player.notify(&GameMessage::Resumed(game_id));
+        player.notify(&GameMessage::GameState(self.game.view()));
+        if !self.game.chat.is_empty() {
+            player.notify(&GameMessage::ChatHistory(self.game.chat_history()));
+        }
+        self.game
+            .broadcast_to_players(GameMessage::PlayerReconnected(player.get_name()));
+
+        // A game restored after a restart carries on once every seat is back
+        let ready =
+            self.game.players.len() == 2 && self.game.players.iter().all(|p| p.is_connected());
+        if self.game.get_status() == GameStatus::WaitingForPlayers && ready {
+            self.game.set_status(GameStatus::InProgress);
+            self.game
+                .broadcast_to_players(GameMessage::GameState(self.game.view()));
+            self.play_bot_turns();
+            self.relist();
+        }
+        Ok(player)
+    }
+
+    fn make_move(&mut self, player_id: &str, position: usize) {
+        // A move that arrives after the deadline, before the check has run, is too late
+        self.flag();
+        match self.game.get_status() {
+            GameStatus::InProgress => match self.apply_move(player_id, position) {
+                Ok(()) => {
+                    self.play_bot_turns();
+                    // Finished games drop out of the lobby
+                    if self.game.get_status() == GameStatus::Finished {
+                        self.relist();
+                    }
+                }
+                Err(e) => self.reply(player_id, e.into()),
+            },
+            GameStatus::WaitingForPlayers => self.reply(player_id, GameMessage::WaitingForPlayers),
+            // Only the sender is told; everyone already saw how the round ended
+            GameStatus::Finished => {
+                let error = GameError::AlreadyFinished(self.game.get_id());
+                self.reply(player_id, error.into());
+            }
+        }
+    }
+
+    fn say(&mut self, player_id: &str, text: &str) {
+        match self.game.chat(player_id, text) {
+            Ok(message) => self.game.broadcast_to_players(GameMessage::Chat(message)),
+            Err(e) => self.reply(player_id, e.into()),
+        }
+    }
+
+    // Either player may offer a rematch once a round is over; the next round starts when
+    // the other agrees. Bots always agree.
+    fn restart(&mut self, player_id: &str) {
+        if self.game.get_status() != GameStatus::Finished {
+            self.reply(player_id, GameMessage::CantRestart);
+            return;
+        }
+        if self.game.match_over() {
+            self.reply(player_id, GameError::MatchOver.into());
+            return;
+        }
+        match &self.game.rematch_offer {
+            Some(offer) if offer == player_id => {
+                self.reply(player_id, GameError::RematchPending.into());
+                return;
+            }
+            Some(_) => {}
+            None => {
+                let against_bot = self
+                    .game
+                    .players
+                    .iter()
+                    .any(|p| p.get_id() != player_id && p.is_bot());
+                if !against_bot {
+                    let name = match self.game.players.iter().find(|p| p.get_id() == player_id) {
+                        Some(player) => player.get_name(),
+                        None => return,
+                    };
+                    self.game.rematch_offer = Some(player_id.to_string());
+                    self.game
+                        .broadcast_to_players(GameMessage::RematchOffered(name));
+                    return;
+                }
+            }
+        }
+        self.game.rematch();
+        self.game.broadcast_to_players(GameMessage::GameRestarted);
+        let status = self.game.view();
+        self.game
+            .broadcast_to_players(GameMessage::GameState(status));
+        self.record(GameEvent::Restarted);
+        self.play_bot_turns();
+        self.relist();
+    }
+
+    fn request_takeback(&mut self, player_id: &str) {
+        let config = self.server.config();
+        let request = match self.game.request_takeback(player_id, config.max_takebacks) {
+            Ok(request) => request,
+            Err(e) => return self.reply(player_id, e.into()),
+        };
+        let name = match self.game.players.iter().find(|p| p.get_id() == player_id) {
+            Some(player) => player.get_name(),
+            None => return,
+        };
+        let timeout = config.takeback_timeout;
+        self.game
+            .broadcast_to_players(GameMessage::TakebackRequested(name, timeout.as_secs()));
+
+        // Bots always agree
+        let bot = self
+            .game
+            .players
+            .iter()
+            .find(|p| p.get_id() != player_id && p.is_bot())
+            .map(|p| p.get_id());
+        if let Some(bot_id) = bot {
+            self.answer_takeback(&bot_id, true);
+            return;
+        }
+
+        let commands = self.commands.clone();
+        tokio::spawn(async move {
+            sleep(timeout).await;
+            let _ = commands.send(GameCommand::ExpireTakeback {
+                request_id: request.id,
+            });
+        });
+    }
+
+    // Only the requester's opponent may answer
+    fn answer_takeback(&mut self, player_id: &str, accept: bool) {
+        let requester = match &self.game.takeback {
+            Some(request) if request.player_id != player_id => request.player_id.clone(),
+            _ => return self.reply(player_id, GameError::NoTakebackRequest.into()),
+        };
+        let (name, symbol) = match (
+            self.game.players.iter().find(|p| p.get_id() == player_id),
+            self.game.players.iter().find(|p| p.get_id() == requester),
+        ) {
+            (Some(answerer), Some(requester)) => (answerer.get_name(), requester.get_symbol()),
+            _ => return,
+        };
+
+        if !accept {
+            self.game.takeback = None;
+            self.game
+                .broadcast_to_players(GameMessage::TakebackDeclined(name));
+            return;
+        }
+        let undone = self.game.take_back(&symbol);
+        self.record(GameEvent::TookBack { symbol });
+        self.game
+            .broadcast_to_players(GameMessage::TakebackAccepted(name, undone));
+        self.game
+            .broadcast_to_players(GameMessage::GameState(self.game.view()));
+    }
+
+    fn expire_takeback(&mut self, request_id: &str) {
+        let requester = match &self.game.takeback {
+            Some(request) if request.id == request_id => request.player_id.clone(),
+            _ => return,
+        };
+        self.game.takeback = None;
+        if let Some(player) = self.game.players.iter().find(|p| p.get_id() == requester) {
+            self.game
+                .broadcast_to_players(GameMessage::TakebackExpired(player.get_name()));
+        }
+    }
+
+    fn leave(&mut self, participant_id: &str) {
+        let spectator = self
+            .game
+            .spectators
+            .iter()
+            .find(|s| s.get_id() == participant_id)
+            .map(|s| s.get_name());
+        match spectator {
+            Some(name) => {
+                self.game.remove_spectator(participant_id);
+                let count = self.game.spectator_count();
+                self.game
+                    .broadcast_to_players(GameMessage::SpectatorLeft(name.clone(), count));
+                info!(
+                    "Spectator {} stopped watching game {}",
+                    name,
+                    self.game.get_id()
+                );
+                self.relist();
+            }
+            None => self.vacate(participant_id),
+        }
+    }
+
+    // A dropped or idle connection keeps the seat for a grace period so the player can
+    // resume with their token; leaving with `exit` still gives it up at once.
+    fn connection_lost(&mut self, player_id: &str, connection_id: &str) {
+        let name = match self.game.get_player_mut(player_id) {
+            Some(player) => match player.mark_disconnected(connection_id) {
+                true => player.get_name(),
+                false => return,
+            },
+            // The seat was already given up or taken over by a newer connection
+            None => return,
+        };
+        self.game
+            .broadcast_to_players(GameMessage::PlayerAwaitingReconnect(
+                name.clone(),
+                RECONNECT_GRACE_SECS,
+            ));
+        warn!(
+            "Player {} lost connection; holding seat in game {} for {}s",
+            name,
+            self.game.get_id(),
+            RECONNECT_GRACE_SECS
+        );
+
+        self.hold_seat(player_id, connection_id);
+    }
+
+    // Gives the seat up unless its player resumes within the grace period
+    fn hold_seat(&self, player_id: &str, connection_id: &str) {
+        let commands = self.commands.clone();
+        let player_id = player_id.to_string();
+        let connection_id = connection_id.to_string();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(RECONNECT_GRACE_SECS)).await;
+            let _ = commands.send(GameCommand::ExpireSeat {
+                player_id,
+                connection_id,
+            });
+        });
+    }
+
+    fn expire_seat(&mut self, player_id: &str, connection_id: &str) {
+        let expired = self
+            .game
+            .players
+            .iter()
+            .find(|p| p.get_id() == player_id)
+            .filter(|p| !p.is_connected() && p.get_connection_id() == connection_id)
+            .map(|p| p.get_name());
+        if let Some(name) = expired {
+            info!(
+                "Resume window for {} in game {} expired",
+                name,
+                self.game.get_id()
+            );
+            self.vacate(player_id);
+        }
+    }
+
+    // Removes a player from the game, closing it once only bots are left
+    fn vacate(&mut self, player_id: &str) {
+        let (name, token) = match self.game.players.iter().find(|p| p.get_id() == player_id) {
+            Some(player) => (player.get_name(), player.get_resume_token()),
+            None => return,
+        };
+        // Walking out of a round that has started loses it
+        if self.game.get_status() == GameStatus::InProgress && !self.game.moves.is_empty() {
+            let winner = self
+                .game
+                .players
+                .iter()
+                .find(|p| p.get_id() != player_id)
+                .map(|p| p.get_symbol());
+            self.server.metrics().round_finished(Outcome::Abandoned);
+            self.rate(winner, Ending::Abandoned);
+        }
+        self.game.players.retain(|p| p.get_id() != player_id);
+        self.server.forget_token(&token);
+        self.record(GameEvent::Left {
+            resume_token: token,
+        });
+        self.game
+            .broadcast_to_players(GameMessage::PlayerDisconnected(name.clone()));
+        warn!("Player {} disconnected.", name);
+        self.game.reset_series();
+        self.game.reset();
+        // Bots never leave on their own, so drop the game once no human is seated
+        if self.game.players.iter().all(|p| p.is_bot()) {
+            self.game.broadcast_to_players(GameMessage::GameClosed);
+            self.server.remove_game(&self.game.get_id());
+            self.closed = true;
+        } else {
+            self.relist();
+        }
+    }
+
+    // Ends the game at once for its players and spectators. A round cut short counts as
+    // abandoned, but nobody's rating changes.
+    fn close(&mut self, reason: Option<String>) {
+        if self.game.get_status() == GameStatus::InProgress && !self.game.moves.is_empty() {
+            self.server.metrics().round_finished(Outcome::Abandoned);
+        }
+        self.game
+            .broadcast_to_players(GameMessage::ClosedByAdmin(reason.clone()));
+        warn!(
+            "Game {} closed by an administrator{}",
+            self.game.get_id(),
+            reason.map(|r| format!(": {}", r)).unwrap_or_default()
+        );
+        self.server.remove_game(&self.game.get_id());
+        self.closed = true;
+    }
+
+    // Stops the game without recording it as closed, so a file store restores it on the
+    // next start
+    fn abort(&mut self) -> bool {
+        self.closed = true;
+        if self.game.get_status() != GameStatus::InProgress {
+            return false;
+        }
+        let resumable = self.server.config().store.is_some();
+        if !resumable && !self.game.moves.is_empty() {
+            self.server.metrics().round_finished(Outcome::Abandoned);
+        }
+        self.game
+            .broadcast_to_players(GameMessage::GameAborted(resumable));
+        warn!(
+            "Game {} stopped by the shutdown{}",
+            self.game.get_id(),
+            if resumable { ", saved to resume" } else { "" }
+        );
+        true
+    }
+
+    // Plays a validated move, records it and tells everyone the new board
+    fn apply_move(&mut self, player_id: &str, position: usize) -> Result<(), GameError> {
+        let state = self.game.make_move(player_id, position)?;
+        self.server.metrics().moves.inc();
+        // Playing on instead of answering turns a takeback down
+        if self.game.takeback.take().is_some() {
+            self.game
+                .broadcast_to_players(GameMessage::TakebackCancelled);
+        }
+        if let Some(record) = self.game.moves.last() {
+            self.record(GameEvent::moved(record));
+        }
+        self.game
+            .broadcast_to_players(GameMessage::GameState(state));
+        if self.game.get_status() == GameStatus::Finished {
+            self.record(GameEvent::finished(&self.game.outcome()));
+            info!(
+                "Game finished:\n{}\nMoves: {}",
+                PlainRenderer.render(&self.game),
+                replay::notation(&self.game.config, &self.game.moves)
+            );
+            let outcome = match self.game.outcome().winner() {
+                Some(symbol) => Outcome::won_by(symbol),
+                None => Outcome::Draw,
+            };
+            self.server.metrics().round_finished(outcome);
+            self.rate(self.game.outcome().winner().cloned(), Ending::Board);
+            self.game.broadcast_to_players(self.game_over())
+        }
+        Ok(())
+    }
+
+    // Runs the clock of the player to move while the round is being played with every
+    // seat connected, and schedules a check for when their time runs out
+    fn sync_clock(&mut self) {
+        let playing = self.game.get_status() == GameStatus::InProgress
+            && self.game.players.iter().all(|p| p.is_connected());
+        let clock = &mut self.game.clock;
+        if !playing {
+            clock.pause();
+            return;
+        }
+        let seat = self.game.current_turn;
+        if clock.to_move() != Some(seat) {
+            clock.start(seat);
+        } else if !clock.is_running() {
+            clock.resume();
+        } else {
+            return;
+        }
+
+        // Bots move as soon as it's their turn
+        let bot = self.game.players.get(seat).is_some_and(|p| p.is_bot());
+        let left = match self.game.clock.time_left(seat) {
+            Some(left) if !bot => left,
+            _ => return,
+        };
+        let turn = self.game.clock.turn();
+        let commands = self.commands.clone();
+        tokio::spawn(async move {
+            sleep(left).await;
+            let _ = commands.send(GameCommand::CheckClock { turn });
+        });
+    }
+
+    // Ends the round with a loss for the player to move if their time has run out
+    fn flag(&mut self) {
+        if self.game.get_status() != GameStatus::InProgress || !self.game.clock.expired() {
+            return;
+        }
+        let (name, symbol) = match self.game.current_player() {
+            Some(player) => (player.get_name(), player.get_symbol()),
+            None => return,
+        };
+        if self.game.takeback.is_some() {
+            self.game
+                .broadcast_to_players(GameMessage::TakebackCancelled);
+        }
+        self.game.lose_on_time(&symbol);
+        self.record(GameEvent::LostOnTime {
+            symbol: symbol.clone(),
+        });
+        self.record(GameEvent::finished(&self.game.outcome()));
+        info!(
+            "{} ({}) lost on time in game {}",
+            name,
+            symbol,
+            self.game.get_id()
+        );
+        self.game
+            .broadcast_to_players(GameMessage::LostOnTime(name));
+        self.game
+            .broadcast_to_players(GameMessage::GameState(self.game.view()));
+        if let Some(winner) = self.game.outcome().winner() {
+            self.server
+                .metrics()
+                .round_finished(Outcome::won_by(winner));
+        }
+        self.rate(self.game.outcome().winner().cloned(), Ending::Time);
+        self.game.broadcast_to_players(self.game_over());
+        self.relist();
+    }
+
+    // A finished round either ends the match or leaves the players to agree on a rematch
+    fn game_over(&self) -> GameMessage {
+        if self.game.match_over() {
+            GameMessage::MatchOver(self.game.series_leader().map(|p| p.get_name()))
+        } else {
+            GameMessage::GameOver
+        }
+    }
+
+    // Lets any bot whose turn it is reply through the same move path as a human
+    fn play_bot_turns(&mut self) {
+        while self.game.get_status() == GameStatus::InProgress {
+            let (bot_id, symbol, difficulty) = match self.game.current_player() {
+                Some(player) => match player.get_bot_difficulty() {
+                    Some(difficulty) => (player.get_id(), player.get_symbol(), difficulty),
+                    None => return,
+                },
+                None => return,
+            };
+            let position = match bot::choose_move(&self.game, &symbol, difficulty) {
+                Some(position) => position,
+                None => return,
+            };
+            if let Err(e) = self.apply_move(&bot_id, position) {
+                error!("Bot move rejected in game {}: {}", self.game.get_id(), e);
+                return;
+            }
+        }
+    }
+
+    // Counts a round between two people towards their ratings; games against bots, or
+    // between two players of the same name, aren't rated
+    fn rate(&self, winner: Option<PlayerSymbol>, ending: Ending) {
+        let [first, second] = self.game.players.as_slice() else {
+            return;
+        };
+        // Only accounts are rated, so nobody can play under someone else's rating
+        if !first.is_registered()
+            || !second.is_registered()
+            || first.get_name().to_lowercase() == second.get_name().to_lowercase()
+        {
+            return;
+        }
+        let winner = winner
+            .and_then(|symbol| self.game.player_with_symbol(&symbol))
+            .map(|p| p.get_name());
+        let changes = self.server.rate(
+            &self.game.get_id(),
+            [first.get_name(), second.get_name()],
+            winner,
+            ending,
+        );
+        self.game
+            .broadcast_to_players(GameMessage::RatingsUpdated(changes));
+    }
+
+    fn record(&self, event: GameEvent) {
+        self.server.record(&self.game.get_id(), event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::config::ServerConfig;
+    use crate::names::NamePolicy;
+    use crate::outbox::SlowClientPolicy;
+    use crate::storage::MemoryStore;
+    use serde_json::Value;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn server() -> Arc<GameServer> {
+        Arc::new(GameServer::new(
+            ServerConfig::default(),
+            Box::new(MemoryStore::default()),
+            Accounts::in_memory(),
+            NamePolicy::default(),
+        ))
+    }
+
+    fn player(name: &str) -> (Player, Outbox) {
+        let outbox = Outbox::new(64, SlowClientPolicy::DropStale);
+        let player = Player::new(
+            name.to_string(),
+            PlayerSymbol::X,
+            Protocol::Json,
+            outbox.clone(),
+        );
+        (player, outbox)
+    }
+
+    // The events queued for a client so far, as `type` and, for errors, the code
+    async fn events(outbox: &Outbox) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Ok(Some(outgoing)) = tokio::time::timeout(Duration::ZERO, outbox.next()).await {
+            if let Message::Text(text) = outgoing.frame {
+                let event: Value = serde_json::from_str(&text).unwrap();
+                let kind = event["type"].as_str().unwrap().to_string();
+                match event["code"].as_str() {
+                    Some(code) => events.push(format!("{}:{}", kind, code)),
+                    None => events.push(kind),
+                }
+            }
+        }
+        events
+    }
+
+    // Two players in a started game; `alice` moves first
+    async fn started() -> (GameHandle, Player, Outbox, Player, Outbox) {
+        let (alice, alice_outbox) = player("alice");
+        let (bob, bob_outbox) = player("bob");
+        let handle = spawn(Game::new(alice.clone(), BoardConfig::default()), server());
+        let joined = handle.request(|reply| GameCommand::Join {
+            player: bob.clone(),
+            reply,
+        });
+        assert_eq!(joined.await, Some(Ok(())));
+        (handle, alice, alice_outbox, bob, bob_outbox)
+    }
+
+    async fn current_turn(handle: &GameHandle) -> Option<String> {
+        handle
+            .request(|reply| GameCommand::CurrentTurn { reply })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_second_player_starts_the_game() {
+        let (handle, alice, alice_outbox, _, bob_outbox) = started().await;
+        assert_eq!(current_turn(&handle).await, Some(alice.get_id()));
+        assert!(events(&alice_outbox).await.contains(&"state".to_string()));
+        assert!(events(&bob_outbox).await.contains(&"state".to_string()));
+
+        let (carol, _) = player("carol");
+        let joined = handle.request(|reply| GameCommand::Join {
+            player: carol,
+            reply,
+        });
+        assert!(matches!(joined.await, Some(Err(GameError::Full(_)))));
+    }
+
+    #[tokio::test]
+    async fn moves_pass_the_turn_and_out_of_turn_moves_are_refused() {
+        let (handle, alice, alice_outbox, bob, bob_outbox) = started().await;
+        events(&alice_outbox).await;
+        events(&bob_outbox).await;
+
+        handle.send(GameCommand::Move {
+            player_id: bob.get_id(),
+            position: 0,
+        });
+        assert_eq!(current_turn(&handle).await, Some(alice.get_id()));
+        assert_eq!(events(&bob_outbox).await, vec!["error:not_your_turn"]);
+        assert!(events(&alice_outbox).await.is_empty());
+
+        handle.send(GameCommand::Move {
+            player_id: alice.get_id(),
+            position: 4,
+        });
+        assert_eq!(current_turn(&handle).await, Some(bob.get_id()));
+        handle.send(GameCommand::Move {
+            player_id: bob.get_id(),
+            position: 4,
+        });
+        assert_eq!(current_turn(&handle).await, Some(bob.get_id()));
+        assert!(events(&bob_outbox)
+            .await
+            .contains(&"error:invalid_move".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_lost_connection_holds_the_seat_but_leaving_gives_it_up() {
+        let (handle, alice, _, _, bob_outbox) = started().await;
+        handle.send(GameCommand::ConnectionLost {
+            player_id: alice.get_id(),
+            connection_id: alice.get_connection_id(),
+        });
+        assert_eq!(current_turn(&handle).await, Some(alice.get_id()));
+        assert!(events(&bob_outbox)
+            .await
+            .contains(&"player_awaiting_reconnect".to_string()));
+
+        handle.send(GameCommand::Leave {
+            participant_id: alice.get_id(),
+        });
+        assert_eq!(current_turn(&handle).await, None);
+    }
+}
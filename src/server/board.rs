@@ -0,0 +1,289 @@
+// board.rs
+
+use crate::player::PlayerSymbol;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Directions checked for k-in-a-row: right, down, down-right, down-left
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// Board dimensions and the number of symbols in a row needed to win (an m,n,k-game).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoardConfig {
+    pub rows: usize,
+    pub cols: usize,
+    pub win_length: usize,
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        BoardConfig {
+            rows: 3,
+            cols: 3,
+            win_length: 3,
+        }
+    }
+}
+
+impl BoardConfig {
+    pub const MIN_SIDE: usize = 3;
+    // Columns are named with a single letter in algebraic coordinates
+    pub const MAX_SIDE: usize = 26;
+
+    pub fn new(rows: usize, cols: usize, win_length: usize) -> Result<Self, String> {
+        BoardConfig {
+            rows,
+            cols,
+            win_length,
+        }
+        .validate()
+    }
+
+    pub fn validate(self) -> Result<Self, String> {
+        let sides = Self::MIN_SIDE..=Self::MAX_SIDE;
+        if !sides.contains(&self.rows) || !sides.contains(&self.cols) {
+            return Err(format!(
+                "Board sides must be between {} and {}",
+                Self::MIN_SIDE,
+                Self::MAX_SIDE
+            ));
+        }
+        if self.win_length < Self::MIN_SIDE || self.win_length > self.rows.max(self.cols) {
+            return Err(format!(
+                "Win length must be between {} and {}",
+                Self::MIN_SIDE,
+                self.rows.max(self.cols)
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Parses `N` (an NxN board), `N K`, `ROWS COLS K`, `RxC` or `RxC K`.
+    /// An empty answer keeps the classic board.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let usage = || "Enter `N`, `N K`, `ROWS COLS K` or `RxC K`".to_string();
+        let number = |part: &str| part.parse::<usize>().map_err(|_| usage());
+        let text = text.trim().to_lowercase();
+        let parts: Vec<&str> = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|part| !part.is_empty())
+            .collect();
+
+        let (rows, cols, win_length) = match parts.as_slice() {
+            [] => return Ok(Self::default()),
+            [dims, rest @ ..] if dims.contains('x') => {
+                let (rows, cols) = dims.split_once('x').ok_or_else(usage)?;
+                let (rows, cols) = (number(rows)?, number(cols)?);
+                match rest {
+                    [] => (rows, cols, rows.max(cols).min(5)),
+                    [k] => (rows, cols, number(k)?),
+                    _ => return Err(usage()),
+                }
+            }
+            [side] => {
+                let side = number(side)?;
+                (side, side, side.min(5))
+            }
+            [side, k] => (number(side)?, number(side)?, number(k)?),
+            [rows, cols, k] => (number(rows)?, number(cols)?, number(k)?),
+            _ => return Err(usage()),
+        };
+        Self::new(rows, cols, win_length)
+    }
+
+    pub fn cells(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    /// Boards of up to 9 cells keep the classic 1-9 numbering; larger ones use coordinates.
+    pub fn is_classic(&self) -> bool {
+        self.cells() <= 9
+    }
+
+    /// Parses a cell entered by a text client into a board index.
+    pub fn parse_cell(&self, text: &str) -> Option<usize> {
+        let text = text.trim().to_lowercase();
+        if self.is_classic() {
+            return match text.parse::<usize>() {
+                Ok(position) if (1..=self.cells()).contains(&position) => Some(position - 1),
+                _ => None,
+            };
+        }
+
+        // Algebraic, e.g. `c3`: column letter then 1-based row
+        let mut chars = text.chars();
+        if let Some(letter @ 'a'..='z') = chars.next() {
+            let col = (letter as u8 - b'a') as usize;
+            let row = chars.as_str().parse::<usize>().ok()?;
+            return self.index(row.checked_sub(1)?, col);
+        }
+
+        // Row and column, e.g. `3 3` or `3,3`, both 1-based
+        let parts: Vec<&str> = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|part| !part.is_empty())
+            .collect();
+        match parts.as_slice() {
+            [row, col] => {
+                let row = row.parse::<usize>().ok()?.checked_sub(1)?;
+                let col = col.parse::<usize>().ok()?.checked_sub(1)?;
+                self.index(row, col)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn index(&self, row: usize, col: usize) -> Option<usize> {
+        if row < self.rows && col < self.cols {
+            Some(row * self.cols + col)
+        } else {
+            None
+        }
+    }
+
+    pub fn column_name(col: usize) -> char {
+        (b'a' + col as u8) as char
+    }
+
+    /// Names a cell the way text clients enter it: `5` on a classic board, `c3` otherwise.
+    pub fn cell_name(&self, index: usize) -> String {
+        if self.is_classic() {
+            (index + 1).to_string()
+        } else {
+            format!(
+                "{}{}",
+                Self::column_name(index % self.cols),
+                index / self.cols + 1
+            )
+        }
+    }
+
+    pub fn move_hint(&self) -> String {
+        if self.is_classic() {
+            format!("Enter a number from 1 to {}", self.cells())
+        } else {
+            "Enter a coordinate like c3 or `row col`".to_string()
+        }
+    }
+
+    /// Finds a run of `win_length` equal symbols. Each run is walked once from its first cell,
+    /// so the scan is linear in the board size rather than enumerating every possible line.
+    pub fn find_winning_line(
+        &self,
+        board: &[Option<PlayerSymbol>],
+    ) -> Option<(PlayerSymbol, Vec<usize>)> {
+        let at = |row: isize, col: isize| -> Option<&PlayerSymbol> {
+            if row < 0 || col < 0 {
+                return None;
+            }
+            self.index(row as usize, col as usize)
+                .and_then(|index| board[index].as_ref())
+        };
+
+        for row in 0..self.rows as isize {
+            for col in 0..self.cols as isize {
+                let symbol = match at(row, col) {
+                    Some(symbol) => symbol,
+                    None => continue,
+                };
+                for (dr, dc) in DIRECTIONS {
+                    // Only start counting at the beginning of a run
+                    if at(row - dr, col - dc) == Some(symbol) {
+                        continue;
+                    }
+                    let mut line = Vec::new();
+                    let (mut r, mut c) = (row, col);
+                    while at(r, c) == Some(symbol) {
+                        line.push(r as usize * self.cols + c as usize);
+                        r += dr;
+                        c += dc;
+                    }
+                    if line.len() >= self.win_length {
+                        line.truncate(self.win_length);
+                        return Some((symbol.clone(), line));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for BoardConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {} in a row",
+            self.rows, self.cols, self.win_length
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A board with `symbol` on each of `cells`
+    fn board_with(
+        config: &BoardConfig,
+        symbol: PlayerSymbol,
+        cells: &[(usize, usize)],
+    ) -> Vec<Option<PlayerSymbol>> {
+        let mut board = vec![None; config.cells()];
+        for &(row, col) in cells {
+            board[config.index(row, col).unwrap()] = Some(symbol.clone());
+        }
+        board
+    }
+
+    #[test]
+    fn finds_a_row_on_a_wide_board() {
+        let config = BoardConfig::new(4, 6, 4).unwrap();
+        let board = board_with(&config, PlayerSymbol::X, &[(3, 2), (3, 3), (3, 4), (3, 5)]);
+        assert_eq!(
+            config.find_winning_line(&board),
+            Some((PlayerSymbol::X, vec![20, 21, 22, 23]))
+        );
+    }
+
+    #[test]
+    fn finds_a_column_on_a_tall_board() {
+        let config = BoardConfig::new(6, 3, 3).unwrap();
+        let board = board_with(&config, PlayerSymbol::O, &[(3, 2), (4, 2), (5, 2)]);
+        assert_eq!(
+            config.find_winning_line(&board),
+            Some((PlayerSymbol::O, vec![11, 14, 17]))
+        );
+    }
+
+    #[test]
+    fn finds_both_diagonals_off_the_main_one() {
+        let config = BoardConfig::new(4, 5, 4).unwrap();
+        let down_right = board_with(&config, PlayerSymbol::X, &[(0, 1), (1, 2), (2, 3), (3, 4)]);
+        assert_eq!(
+            config.find_winning_line(&down_right),
+            Some((PlayerSymbol::X, vec![1, 7, 13, 19]))
+        );
+        let down_left = board_with(&config, PlayerSymbol::X, &[(0, 3), (1, 2), (2, 1), (3, 0)]);
+        assert_eq!(
+            config.find_winning_line(&down_left),
+            Some((PlayerSymbol::X, vec![3, 7, 11, 15]))
+        );
+    }
+
+    #[test]
+    fn ignores_runs_shorter_than_the_win_length() {
+        let config = BoardConfig::new(5, 7, 4).unwrap();
+        let board = board_with(&config, PlayerSymbol::X, &[(2, 0), (2, 1), (2, 2), (0, 6)]);
+        assert_eq!(config.find_winning_line(&board), None);
+    }
+
+    #[test]
+    fn does_not_wrap_around_the_edge() {
+        // The end of one row and the start of the next are neighbours in the cell list
+        let config = BoardConfig::new(3, 4, 3).unwrap();
+        let board = board_with(&config, PlayerSymbol::O, &[(0, 2), (0, 3), (1, 0)]);
+        assert_eq!(config.find_winning_line(&board), None);
+    }
+}
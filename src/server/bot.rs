@@ -0,0 +1,167 @@
+// bot.rs
+
+use crate::game::{Game, GameOutcome};
+use crate::player::PlayerSymbol;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Random,
+    Heuristic,
+    Perfect,
+}
+
+impl Difficulty {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "1" | "easy" | "random" => Some(Difficulty::Random),
+            "2" | "medium" | "heuristic" => Some(Difficulty::Heuristic),
+            "3" | "hard" | "perfect" => Some(Difficulty::Perfect),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Difficulty::Random => "easy",
+            Difficulty::Heuristic => "medium",
+            Difficulty::Perfect => "hard",
+        }
+    }
+}
+
+/// Picks the cell the bot playing `symbol` will take, or `None` if the board is full.
+pub fn choose_move(game: &Game, symbol: &PlayerSymbol, difficulty: Difficulty) -> Option<usize> {
+    match difficulty {
+        Difficulty::Random => random_move(game),
+        Difficulty::Heuristic => heuristic_move(game, symbol),
+        Difficulty::Perfect => perfect_move(game, symbol),
+    }
+}
+
+fn empty_cells(game: &Game) -> Vec<usize> {
+    (0..game.board.len())
+        .filter(|&i| game.board[i].is_none())
+        .collect()
+}
+
+fn opponent(symbol: &PlayerSymbol) -> PlayerSymbol {
+    match symbol {
+        PlayerSymbol::X => PlayerSymbol::O,
+        PlayerSymbol::O => PlayerSymbol::X,
+    }
+}
+
+fn random_move(game: &Game) -> Option<usize> {
+    empty_cells(game).choose(&mut rand::thread_rng()).copied()
+}
+
+// Returns a cell that completes a line for `symbol`, if there is one
+fn winning_cell(game: &Game, symbol: &PlayerSymbol) -> Option<usize> {
+    let mut sim = game.clone();
+    empty_cells(game).into_iter().find(|&cell| {
+        sim.board[cell] = Some(symbol.clone());
+        let wins = matches!(sim.outcome(), GameOutcome::Win { .. });
+        sim.board[cell] = None;
+        wins
+    })
+}
+
+fn heuristic_move(game: &Game, symbol: &PlayerSymbol) -> Option<usize> {
+    // Win, then block, then centre, then corners, then anything. Bots only play the
+    // classic board (see `GameServer::create_bot_game`), so its centre and corners are fixed.
+    winning_cell(game, symbol)
+        .or_else(|| winning_cell(game, &opponent(symbol)))
+        .or_else(|| [4].into_iter().find(|&i| game.board[i].is_none()))
+        .or_else(|| {
+            let corners: Vec<usize> = [0, 2, 6, 8]
+                .into_iter()
+                .filter(|&i| game.board[i].is_none())
+                .collect();
+            corners.choose(&mut rand::thread_rng()).copied()
+        })
+        .or_else(|| random_move(game))
+}
+
+fn perfect_move(game: &Game, symbol: &PlayerSymbol) -> Option<usize> {
+    let mut sim = game.clone();
+    let mut best: Option<(i32, usize)> = None;
+    let mut alpha = i32::MIN + 1;
+    for cell in empty_cells(game) {
+        sim.board[cell] = Some(symbol.clone());
+        let score = -negamax(&mut sim, &opponent(symbol), 1, -i32::MAX, -alpha);
+        sim.board[cell] = None;
+        if best.is_none_or(|(best_score, _)| score > best_score) {
+            best = Some((score, cell));
+        }
+        alpha = alpha.max(score);
+    }
+    best.map(|(_, cell)| cell)
+}
+
+// Scores the position for `to_move`; quicker wins and slower losses score higher
+fn negamax(sim: &mut Game, to_move: &PlayerSymbol, depth: i32, mut alpha: i32, beta: i32) -> i32 {
+    match sim.outcome() {
+        // The previous move won, so the side to move has lost
+        GameOutcome::Win { .. } => return depth - 100,
+        GameOutcome::Draw => return 0,
+        GameOutcome::Ongoing | GameOutcome::WonOnTime { .. } => {}
+    }
+
+    let mut best = i32::MIN + 1;
+    for cell in empty_cells(sim) {
+        sim.board[cell] = Some(to_move.clone());
+        let score = -negamax(sim, &opponent(to_move), depth + 1, -beta, -alpha);
+        sim.board[cell] = None;
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BoardConfig;
+
+    fn game_with(x: &[usize], o: &[usize]) -> Game {
+        let mut game = Game::with_id("g".to_string(), "alice".to_string(), BoardConfig::default());
+        for &cell in x {
+            game.board[cell] = Some(PlayerSymbol::X);
+        }
+        for &cell in o {
+            game.board[cell] = Some(PlayerSymbol::O);
+        }
+        game
+    }
+
+    #[test]
+    fn medium_takes_a_win_before_blocking_a_loss() {
+        // X can finish the top row, O the middle one
+        let game = game_with(&[0, 1], &[3, 4]);
+        assert_eq!(heuristic_move(&game, &PlayerSymbol::X), Some(2));
+        assert_eq!(heuristic_move(&game, &PlayerSymbol::O), Some(5));
+        let game = game_with(&[0, 1], &[4]);
+        assert_eq!(heuristic_move(&game, &PlayerSymbol::O), Some(2));
+    }
+
+    #[test]
+    fn hard_answers_opposite_corners_with_an_edge() {
+        // X in opposite corners, O in the centre: O must take an edge, not a corner
+        let game = game_with(&[0, 8], &[4]);
+        let cell = perfect_move(&game, &PlayerSymbol::O).unwrap();
+        assert!([1, 3, 5, 7].contains(&cell), "took {}", cell);
+    }
+
+    #[test]
+    fn difficulties_parse_by_number_or_name() {
+        assert_eq!(Difficulty::parse(" 2 "), Some(Difficulty::Heuristic));
+        assert_eq!(Difficulty::parse("HARD"), Some(Difficulty::Perfect));
+        assert_eq!(Difficulty::parse("4"), None);
+    }
+}
@@ -0,0 +1,302 @@
+// clock.rs
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Longest per-move limit or bank that can be set up, in seconds
+const MAX_SECS: u64 = 24 * 60 * 60;
+const TOO_LONG: &str = "Time limits can be at most a day";
+
+/// Chess-style time control chosen when a game is created: a limit for each move, a bank
+/// of time for the whole round, and an increment added to the bank after every move.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct TimeControl {
+    #[serde(default)]
+    pub per_move_secs: Option<u64>,
+    #[serde(default)]
+    pub bank_secs: Option<u64>,
+    #[serde(default)]
+    pub increment_secs: u64,
+}
+
+impl TimeControl {
+    pub fn per_move(secs: u64) -> Self {
+        TimeControl {
+            per_move_secs: Some(secs),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the control limits turns at all. The default one, kept by games stored
+    /// before time controls existed, doesn't.
+    pub fn is_timed(&self) -> bool {
+        self.per_move_secs.is_some() || self.bank_secs.is_some()
+    }
+
+    /// Parses `30s` (seconds per move), `5m` (a 5-minute bank) or `5+3` (a 5-minute bank
+    /// plus 3 seconds per move), in any combination, e.g. `5+3 30s`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let usage =
+            || "Enter `30s` per move, `5m` or `5+3` for a bank with an increment".to_string();
+        let number = |part: &str| part.parse::<u64>().map_err(|_| usage());
+        let bank = |minutes: &str| {
+            number(minutes)?
+                .checked_mul(60)
+                .ok_or_else(|| TOO_LONG.to_string())
+        };
+        let mut control = TimeControl::default();
+        for word in text.split_whitespace() {
+            let word = word.to_lowercase();
+            if let Some((minutes, increment)) = word.split_once('+') {
+                control.bank_secs = Some(bank(minutes)?);
+                control.increment_secs = number(increment)?;
+            } else if let Some(minutes) = word.strip_suffix('m') {
+                control.bank_secs = Some(bank(minutes)?);
+            } else if let Some(secs) = word.strip_suffix('s') {
+                control.per_move_secs = Some(number(secs)?);
+            } else {
+                return Err(usage());
+            }
+        }
+        control.validate()
+    }
+
+    /// Whether a word of the board prompt is part of a time control.
+    pub fn is_time_word(word: &str) -> bool {
+        let word = word.to_lowercase();
+        let numeric = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_digit());
+        match word.split_once('+') {
+            Some((minutes, increment)) => numeric(minutes) && numeric(increment),
+            None => word.strip_suffix(['s', 'm']).is_some_and(numeric),
+        }
+    }
+
+    pub fn validate(self) -> Result<Self, String> {
+        if self.per_move_secs.is_none() && self.bank_secs.is_none() {
+            return Err("A time control needs a per-move limit or a bank".to_string());
+        }
+        let limits = [
+            self.per_move_secs,
+            self.bank_secs,
+            Some(self.increment_secs),
+        ];
+        if limits.iter().flatten().any(|&secs| secs > MAX_SECS) {
+            return Err(TOO_LONG.to_string());
+        }
+        if self.per_move_secs == Some(0) || self.bank_secs == Some(0) {
+            return Err("Time limits must be at least 1 second".to_string());
+        }
+        Ok(self)
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(bank) = self.bank_secs {
+            parts.push(format!(
+                "{}+{}",
+                format_duration(Duration::from_secs(bank)),
+                self.increment_secs
+            ));
+        }
+        if let Some(per_move) = self.per_move_secs {
+            parts.push(format!("{}s per move", per_move));
+        }
+        if parts.is_empty() {
+            return write!(f, "no time limit");
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` for an hour or more.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// The time each seat has left. Only the seat to move uses time, and only while the
+/// clock runs; the game pauses it while a player is away.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    // Bank left for each seat, in seat order
+    banks: Vec<Duration>,
+    to_move: Option<usize>,
+    // When the clock last started running for the seat to move
+    since: Option<Instant>,
+    // Time the seat to move already used this turn before the clock last started
+    used: Duration,
+    // Changes whenever the clock starts, so a stale deadline check can be ignored
+    turn: u64,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let bank = Duration::from_secs(control.bank_secs.unwrap_or(0));
+        Clock {
+            control,
+            banks: vec![bank; 2],
+            to_move: None,
+            since: None,
+            used: Duration::ZERO,
+            turn: 0,
+        }
+    }
+
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    pub fn to_move(&self) -> Option<usize> {
+        self.to_move
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// Starts a fresh turn for `seat`. Time already used by another seat stays spent.
+    pub fn start(&mut self, seat: usize) {
+        self.pause();
+        self.to_move = Some(seat);
+        self.used = Duration::ZERO;
+        self.resume();
+    }
+
+    /// Carries on with the turn that was paused.
+    pub fn resume(&mut self) {
+        if self.to_move.is_some() && self.since.is_none() {
+            self.since = Some(Instant::now());
+            self.turn += 1;
+        }
+    }
+
+    pub fn pause(&mut self) {
+        let (seat, since) = match (self.to_move, self.since.take()) {
+            (Some(seat), Some(since)) => (seat, since),
+            _ => return,
+        };
+        let spent = since.elapsed();
+        self.used += spent;
+        if let Some(bank) = self.banks.get_mut(seat) {
+            *bank = bank.saturating_sub(spent);
+        }
+    }
+
+    /// Ends the turn of the seat that just moved and adds the increment to its bank.
+    pub fn moved(&mut self) {
+        self.pause();
+        if let Some(seat) = self.to_move.take() {
+            if let Some(bank) = self.banks.get_mut(seat) {
+                *bank += Duration::from_secs(self.control.increment_secs);
+            }
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.pause();
+        self.to_move = None;
+    }
+
+    /// Fills both banks again for a new round.
+    pub fn reset(&mut self) {
+        *self = Clock {
+            turn: self.turn,
+            ..Clock::new(self.control)
+        };
+    }
+
+    /// Time `seat` has left: the smaller of what remains of its bank and of this move's
+    /// limit. `None` only if the control sets neither.
+    pub fn time_left(&self, seat: usize) -> Option<Duration> {
+        let running = match (self.to_move, self.since) {
+            (Some(to_move), Some(since)) if to_move == seat => since.elapsed(),
+            _ => Duration::ZERO,
+        };
+        let used = if self.to_move == Some(seat) {
+            self.used + running
+        } else {
+            Duration::ZERO
+        };
+        let bank = self.control.bank_secs.map(|_| {
+            self.banks
+                .get(seat)
+                .copied()
+                .unwrap_or_default()
+                .saturating_sub(running)
+        });
+        let per_move = self
+            .control
+            .per_move_secs
+            .map(|secs| Duration::from_secs(secs).saturating_sub(used));
+        match (bank, per_move) {
+            (Some(bank), Some(per_move)) => Some(bank.min(per_move)),
+            (bank, per_move) => bank.or(per_move),
+        }
+    }
+
+    /// Whether the seat to move has run out of time.
+    pub fn expired(&self) -> bool {
+        self.to_move
+            .and_then(|seat| self.time_left(seat))
+            .is_some_and(|left| left.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_kind_of_control() {
+        assert_eq!(TimeControl::parse("30s"), Ok(TimeControl::per_move(30)));
+        let bank = TimeControl::parse("5+3").unwrap();
+        assert_eq!((bank.bank_secs, bank.increment_secs), (Some(300), 3));
+        let both = TimeControl::parse("10M 20s").unwrap();
+        assert_eq!((both.bank_secs, both.per_move_secs), (Some(600), Some(20)));
+    }
+
+    #[test]
+    fn accepts_up_to_a_day() {
+        assert!(TimeControl::parse("1440m").is_ok());
+        assert!(TimeControl::parse("86400s").is_ok());
+        assert!(TimeControl::parse("1+86400").is_ok());
+    }
+
+    #[test]
+    fn turns_down_limits_out_of_range() {
+        for text in ["1441m", "86401s", "1+86401", "0s", "0m", "0+5"] {
+            assert!(TimeControl::parse(text).is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn turns_down_minutes_that_overflow() {
+        // 60 times this is past u64::MAX
+        let minutes = u64::MAX / 60 + 1;
+        assert_eq!(
+            TimeControl::parse(&format!("{}m", minutes)),
+            Err(TOO_LONG.to_string())
+        );
+        assert_eq!(
+            TimeControl::parse(&format!("{}+0", u64::MAX)),
+            Err(TOO_LONG.to_string())
+        );
+        // Too long to be a u64 at all
+        assert!(TimeControl::parse("99999999999999999999999m").is_err());
+    }
+
+    #[test]
+    fn turns_down_other_words() {
+        for text in ["", "5h", "fast", "+3", "5+", "-5m"] {
+            assert!(TimeControl::parse(text).is_err(), "{:?} was accepted", text);
+        }
+    }
+}
@@ -0,0 +1,474 @@
+// config.rs
+
+use crate::outbox::SlowClientPolicy;
+use clap::Parser;
+use serde::Deserialize;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Every setting can come from a flag or a `TICTACTOE_*` environment variable; anything
+// left unset falls back to the config file and then to the defaults.
+#[derive(Debug, Parser)]
+#[command(name = "server", about = "Tic-Tac-Toe WebSocket server")]
+struct Cli {
+    /// Optional TOML config file; relative paths in it are taken from its own directory
+    #[arg(long, env = "TICTACTOE_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "TICTACTOE_BIND")]
+    bind: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(long, env = "TICTACTOE_PORT")]
+    port: Option<u16>,
+    /// Seconds per move in games created without a time control, 0 to leave them untimed
+    #[arg(long, env = "TICTACTOE_TURN_TIMEOUT")]
+    turn_timeout: Option<u64>,
+    /// Seconds a player in an untimed game may stay silent on their own turn
+    #[arg(long, env = "TICTACTOE_IDLE_TIMEOUT")]
+    idle_timeout: Option<u64>,
+    /// Seconds the opponent has to answer a takeback request
+    #[arg(long, env = "TICTACTOE_TAKEBACK_TIMEOUT")]
+    takeback_timeout: Option<u64>,
+    /// Takebacks each player may have accepted per game, 0 to turn them off
+    #[arg(long, env = "TICTACTOE_MAX_TAKEBACKS")]
+    max_takebacks: Option<usize>,
+    /// Games that may exist at once
+    #[arg(long, env = "TICTACTOE_MAX_GAMES")]
+    max_games: Option<usize>,
+    /// Clients that may be connected at once
+    #[arg(long, env = "TICTACTOE_MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+    /// Clients that may be connected at once from one IP address
+    #[arg(long, env = "TICTACTOE_MAX_CONNECTIONS_PER_IP")]
+    max_connections_per_ip: Option<usize>,
+    /// Messages a second each client may send on average
+    #[arg(long, env = "TICTACTOE_MESSAGE_RATE")]
+    message_rate: Option<u32>,
+    /// Messages a client may send in a burst above the average rate
+    #[arg(long, env = "TICTACTOE_MESSAGE_BURST")]
+    message_burst: Option<u32>,
+    /// Largest message, and frame, a client may send, in bytes
+    #[arg(long, env = "TICTACTOE_MAX_MESSAGE_SIZE")]
+    max_message_size: Option<usize>,
+    /// Seconds between active game summaries in the log
+    #[arg(long, env = "TICTACTOE_LOG_INTERVAL")]
+    log_interval: Option<u64>,
+    /// Seconds games may keep playing after SIGINT or SIGTERM before they are stopped
+    #[arg(long, env = "TICTACTOE_SHUTDOWN_GRACE")]
+    shutdown_grace: Option<u64>,
+    /// List every game in the summaries, not just the counts (`true` or `false`)
+    #[arg(long, env = "TICTACTOE_LOG_GAMES")]
+    log_games: Option<bool>,
+    /// Port to serve Prometheus metrics on at `/metrics`; unset serves none
+    #[arg(long, env = "TICTACTOE_METRICS_PORT")]
+    metrics_port: Option<u16>,
+    /// Port to serve the HTTP admin API on; may be the same as the metrics port
+    #[arg(long, env = "TICTACTOE_ADMIN_PORT")]
+    admin_port: Option<u16>,
+    /// Bearer token every admin API request must carry
+    #[arg(long, env = "TICTACTOE_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// Messages that may wait to be sent to one client before its slow-client policy applies
+    #[arg(long, env = "TICTACTOE_OUTBOX_SIZE")]
+    outbox_size: Option<usize>,
+    /// What to do when a client's outbound queue is full
+    #[arg(long, env = "TICTACTOE_SLOW_CLIENT_POLICY", value_enum)]
+    slow_client_policy: Option<SlowClientPolicy>,
+    /// File games are recorded to and restored from, or `:memory:` to keep nothing
+    /// [default: games.jsonl in the working directory]
+    #[arg(long, env = "TICTACTOE_STORE")]
+    store: Option<PathBuf>,
+    /// File registered accounts are kept in, or `:memory:` to keep them only while running
+    /// [default: accounts.json in the working directory]
+    #[arg(long, env = "TICTACTOE_ACCOUNTS")]
+    accounts: Option<PathBuf>,
+    /// Whether players may join without an account (`true` or `false`)
+    #[arg(long, env = "TICTACTOE_ALLOW_GUESTS")]
+    allow_guests: Option<bool>,
+    /// File of words, one per line, that new player names may not contain
+    #[arg(long, env = "TICTACTOE_NAME_BLOCKLIST")]
+    name_blocklist: Option<PathBuf>,
+    /// Certificate for wss://: a PEM chain, or a PKCS#12 bundle (.p12/.pfx) holding the key too
+    #[arg(long, env = "TICTACTOE_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM (PKCS#8) private key for a PEM certificate
+    #[arg(long, env = "TICTACTOE_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// Password for a PKCS#12 bundle
+    #[arg(long, env = "TICTACTOE_TLS_PASSWORD", hide_env_values = true)]
+    tls_password: Option<String>,
+}
+
+// Same settings as the CLI, read from the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind: Option<IpAddr>,
+    port: Option<u16>,
+    turn_timeout: Option<u64>,
+    idle_timeout: Option<u64>,
+    takeback_timeout: Option<u64>,
+    max_takebacks: Option<usize>,
+    max_games: Option<usize>,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    message_rate: Option<u32>,
+    message_burst: Option<u32>,
+    max_message_size: Option<usize>,
+    log_interval: Option<u64>,
+    shutdown_grace: Option<u64>,
+    log_games: Option<bool>,
+    metrics_port: Option<u16>,
+    admin_port: Option<u16>,
+    admin_token: Option<String>,
+    outbox_size: Option<usize>,
+    slow_client_policy: Option<SlowClientPolicy>,
+    store: Option<PathBuf>,
+    accounts: Option<PathBuf>,
+    allow_guests: Option<bool>,
+    name_blocklist: Option<PathBuf>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_password: Option<String>,
+}
+
+impl FileConfig {
+    // Relative paths in the file are taken from the file's own directory, so the same file
+    // finds the same data wherever the server is started from
+    fn resolve_paths(mut self, dir: &Path) -> Self {
+        let paths = [
+            &mut self.store,
+            &mut self.accounts,
+            &mut self.name_blocklist,
+            &mut self.tls_cert,
+            &mut self.tls_key,
+        ];
+        for path in paths.into_iter().flatten() {
+            if path.is_relative() && path.as_os_str() != MEMORY_STORE {
+                *path = dir.join(&path);
+            }
+        }
+        self
+    }
+}
+
+// `store` and `accounts` value that keeps data in memory instead of a file
+const MEMORY_STORE: &str = ":memory:";
+
+// Room for any command a client needs to send, such as an `auth` with a key signature
+const MIN_MESSAGE_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Where the server's TLS identity is loaded from.
+#[derive(Debug, Clone)]
+pub enum TlsConfig {
+    Pem { cert: PathBuf, key: PathBuf },
+    Pkcs12 { bundle: PathBuf, password: String },
+}
+
+/// Settings the server is started with.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+    pub turn_timeout: Duration,
+    pub idle_timeout: Duration,
+    pub takeback_timeout: Duration,
+    // Per player and game; 0 turns takebacks off
+    pub max_takebacks: usize,
+    pub max_games: usize,
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    // Each connection's token bucket: `message_rate` a second, bursts of `message_burst`
+    pub message_rate: u32,
+    pub message_burst: u32,
+    // Bytes; larger messages or frames close the connection
+    pub max_message_size: usize,
+    pub log_interval: Duration,
+    // How long games may finish after a shutdown signal; 0 stops them at once
+    pub shutdown_grace: Duration,
+    // List every game in the summaries rather than only count them
+    pub log_games: bool,
+    // Serve `/metrics` on this port, on the same address as the game server
+    pub metrics_port: Option<u16>,
+    // Serve the admin API on this port, to requests carrying `admin_token`
+    pub admin_port: Option<u16>,
+    pub admin_token: Option<String>,
+    pub outbox_size: usize,
+    pub slow_client_policy: SlowClientPolicy,
+    // Append-only game log; `None` keeps games in memory only
+    pub store: Option<PathBuf>,
+    // Registered accounts; `None` keeps them in memory only
+    pub accounts: Option<PathBuf>,
+    // Whether players may pick a name without signing in
+    pub allow_guests: bool,
+    // Words new names may not contain; `None` blocks none
+    pub name_blocklist: Option<PathBuf>,
+    // Serve wss:// instead of ws:// when set
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            turn_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(300),
+            takeback_timeout: Duration::from_secs(30),
+            max_takebacks: 3,
+            max_games: 1000,
+            max_connections: 1000,
+            max_connections_per_ip: 20,
+            message_rate: 10,
+            message_burst: 20,
+            max_message_size: 4096,
+            log_interval: Duration::from_secs(10),
+            shutdown_grace: Duration::from_secs(30),
+            log_games: false,
+            metrics_port: None,
+            admin_port: None,
+            admin_token: None,
+            outbox_size: 64,
+            slow_client_policy: SlowClientPolicy::DropStale,
+            store: Some(PathBuf::from("games.jsonl")),
+            accounts: Some(PathBuf::from("accounts.json")),
+            allow_guests: true,
+            name_blocklist: None,
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the command line, environment and config file, in that order of precedence.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = Cli::parse();
+        let file = match &cli.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| ConfigError(format!("can't read {}: {}", path.display(), e)))?;
+                let file: FileConfig = toml::from_str(&text)
+                    .map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?;
+                file.resolve_paths(path.parent().unwrap_or(Path::new("")))
+            }
+            None => FileConfig::default(),
+        };
+
+        let tls = tls_config(
+            cli.tls_cert.or(file.tls_cert),
+            cli.tls_key.or(file.tls_key),
+            cli.tls_password.or(file.tls_password),
+        )?;
+
+        let defaults = ServerConfig::default();
+        let secs = |value: Option<u64>, default: Duration| {
+            value.map(Duration::from_secs).unwrap_or(default)
+        };
+        ServerConfig {
+            bind: cli.bind.or(file.bind).unwrap_or(defaults.bind),
+            port: cli.port.or(file.port).unwrap_or(defaults.port),
+            turn_timeout: secs(
+                cli.turn_timeout.or(file.turn_timeout),
+                defaults.turn_timeout,
+            ),
+            idle_timeout: secs(
+                cli.idle_timeout.or(file.idle_timeout),
+                defaults.idle_timeout,
+            ),
+            takeback_timeout: secs(
+                cli.takeback_timeout.or(file.takeback_timeout),
+                defaults.takeback_timeout,
+            ),
+            max_takebacks: cli
+                .max_takebacks
+                .or(file.max_takebacks)
+                .unwrap_or(defaults.max_takebacks),
+            max_games: cli
+                .max_games
+                .or(file.max_games)
+                .unwrap_or(defaults.max_games),
+            max_connections: cli
+                .max_connections
+                .or(file.max_connections)
+                .unwrap_or(defaults.max_connections),
+            max_connections_per_ip: cli
+                .max_connections_per_ip
+                .or(file.max_connections_per_ip)
+                .unwrap_or(defaults.max_connections_per_ip),
+            message_rate: cli
+                .message_rate
+                .or(file.message_rate)
+                .unwrap_or(defaults.message_rate),
+            message_burst: cli
+                .message_burst
+                .or(file.message_burst)
+                .unwrap_or(defaults.message_burst),
+            max_message_size: cli
+                .max_message_size
+                .or(file.max_message_size)
+                .unwrap_or(defaults.max_message_size),
+            log_interval: secs(
+                cli.log_interval.or(file.log_interval),
+                defaults.log_interval,
+            ),
+            shutdown_grace: secs(
+                cli.shutdown_grace.or(file.shutdown_grace),
+                defaults.shutdown_grace,
+            ),
+            log_games: cli
+                .log_games
+                .or(file.log_games)
+                .unwrap_or(defaults.log_games),
+            metrics_port: cli.metrics_port.or(file.metrics_port),
+            admin_port: cli.admin_port.or(file.admin_port),
+            admin_token: cli.admin_token.or(file.admin_token),
+            outbox_size: cli
+                .outbox_size
+                .or(file.outbox_size)
+                .unwrap_or(defaults.outbox_size),
+            slow_client_policy: cli
+                .slow_client_policy
+                .or(file.slow_client_policy)
+                .unwrap_or(defaults.slow_client_policy),
+            store: match cli.store.or(file.store) {
+                Some(path) if path.as_os_str() == MEMORY_STORE => None,
+                Some(path) => Some(path),
+                None => defaults.store,
+            },
+            accounts: match cli.accounts.or(file.accounts) {
+                Some(path) if path.as_os_str() == MEMORY_STORE => None,
+                Some(path) => Some(path),
+                None => defaults.accounts,
+            },
+            allow_guests: cli
+                .allow_guests
+                .or(file.allow_guests)
+                .unwrap_or(defaults.allow_guests),
+            name_blocklist: cli.name_blocklist.or(file.name_blocklist),
+            tls,
+        }
+        .validate()
+    }
+
+    pub fn validate(self) -> Result<Self, ConfigError> {
+        if self.port == 0 {
+            return Err(ConfigError("port must not be 0".to_string()));
+        }
+        match self.metrics_port {
+            Some(0) => return Err(ConfigError("metrics_port must not be 0".to_string())),
+            Some(port) if port == self.port => {
+                return Err(ConfigError(
+                    "metrics_port must differ from port".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        match self.admin_port {
+            Some(0) => return Err(ConfigError("admin_port must not be 0".to_string())),
+            Some(port) if port == self.port => {
+                return Err(ConfigError("admin_port must differ from port".to_string()))
+            }
+            Some(_) if self.admin_token.as_deref().is_none_or(str::is_empty) => {
+                return Err(ConfigError(
+                    "admin_port is set without an admin_token".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        // A turn timeout of 0 leaves games untimed, so it isn't checked here
+        let timeouts = [
+            ("idle_timeout", self.idle_timeout),
+            ("takeback_timeout", self.takeback_timeout),
+            ("log_interval", self.log_interval),
+        ];
+        for (name, value) in timeouts {
+            if value.is_zero() {
+                return Err(ConfigError(format!("{} must be at least 1 second", name)));
+            }
+        }
+        if self.max_games == 0 || self.max_connections == 0 || self.max_connections_per_ip == 0 {
+            return Err(ConfigError(
+                "max_games, max_connections and max_connections_per_ip must be at least 1"
+                    .to_string(),
+            ));
+        }
+        if self.message_rate == 0 || self.message_burst == 0 {
+            return Err(ConfigError(
+                "message_rate and message_burst must be at least 1".to_string(),
+            ));
+        }
+        if self.max_message_size < MIN_MESSAGE_SIZE {
+            return Err(ConfigError(format!(
+                "max_message_size must be at least {} bytes",
+                MIN_MESSAGE_SIZE
+            )));
+        }
+        if self.outbox_size == 0 {
+            return Err(ConfigError("outbox_size must be at least 1".to_string()));
+        }
+        Ok(self)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_port
+            .map(|port| SocketAddr::new(self.bind, port))
+    }
+
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_port.map(|port| SocketAddr::new(self.bind, port))
+    }
+
+    pub fn url(&self) -> String {
+        let scheme = if self.tls.is_some() { "wss" } else { "ws" };
+        format!("{}://{}", scheme, self.addr())
+    }
+}
+
+// A `.p12`/`.pfx` certificate is a PKCS#12 bundle; anything else is PEM and needs a key
+fn tls_config(
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    password: Option<String>,
+) -> Result<Option<TlsConfig>, ConfigError> {
+    let cert = match cert {
+        Some(cert) => cert,
+        None if key.is_some() => {
+            return Err(ConfigError("tls_key is set without tls_cert".to_string()))
+        }
+        None => return Ok(None),
+    };
+    let is_pkcs12 = cert
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("p12") || ext.eq_ignore_ascii_case("pfx"));
+    match (is_pkcs12, key) {
+        (true, None) => Ok(Some(TlsConfig::Pkcs12 {
+            bundle: cert,
+            password: password.unwrap_or_default(),
+        })),
+        (true, Some(_)) => Err(ConfigError(
+            "a PKCS#12 bundle already holds its key, remove tls_key".to_string(),
+        )),
+        (false, Some(key)) => Ok(Some(TlsConfig::Pem { cert, key })),
+        (false, None) => Err(ConfigError(
+            "a PEM tls_cert needs a tls_key (or use a .p12/.pfx bundle)".to_string(),
+        )),
+    }
+}
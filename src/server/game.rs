@@ -1,34 +1,299 @@
 // game.rs
 
+use crate::board::BoardConfig;
+use crate::clock::{Clock, TimeControl};
 use crate::player::{Player, PlayerSymbol};
-use futures::SinkExt;
-use tokio_tungstenite::tungstenite::protocol::Message;
+use crate::protocol::{ChatMessage, ErrorCode, GameMessage, GameView};
+use crate::render::{AnsiRenderer, JsonRenderer, Renderer};
+use crate::utils::is_unprintable;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, PartialEq)]
+// Longest best-of match that can be set up
+pub const MAX_BEST_OF: usize = 99;
+// Longest chat message accepted, in characters
+pub const CHAT_MAX_LENGTH: usize = 280;
+// Recent chat kept with the game for spectators and reconnecting players
+pub const CHAT_HISTORY: usize = 20;
+// Each player may send this many messages per window
+const CHAT_RATE_LIMIT: usize = 5;
+const CHAT_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum GameStatus {
     WaitingForPlayers,
     InProgress,
     Finished,
 }
 
+/// Result of a game as decided by the rules, independent of how it is displayed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameOutcome {
+    Ongoing,
+    Win {
+        symbol: PlayerSymbol,
+        line: Vec<usize>,
+    },
+    Draw,
+    // The other player ran out of time
+    WonOnTime {
+        symbol: PlayerSymbol,
+    },
+}
+
+impl GameOutcome {
+    pub fn winner(&self) -> Option<&PlayerSymbol> {
+        match self {
+            GameOutcome::Win { symbol, .. } | GameOutcome::WonOnTime { symbol } => Some(symbol),
+            GameOutcome::Ongoing | GameOutcome::Draw => None,
+        }
+    }
+}
+
+/// What the creator chose when setting up a game.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GameSettings {
+    pub board: BoardConfig,
+    pub best_of: Option<usize>,
+    // `None` plays with the server's per-move limit
+    pub time_control: Option<TimeControl>,
+}
+
+impl GameSettings {
+    pub fn validate(self) -> Result<Self, String> {
+        let board = self.board.validate()?;
+        if let Some(best_of) = self.best_of {
+            if !(1..=MAX_BEST_OF).contains(&best_of) {
+                return Err(format!(
+                    "A match can be best of 1 to {} rounds",
+                    MAX_BEST_OF
+                ));
+            }
+        }
+        let time_control = self.time_control.map(TimeControl::validate).transpose()?;
+        Ok(GameSettings {
+            board,
+            best_of: self.best_of,
+            time_control,
+        })
+    }
+}
+
+/// A move as played, kept in order for the game's replay.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MoveRecord {
+    pub player: String,
+    pub symbol: PlayerSymbol,
+    pub cell: usize,
+    pub at: DateTime<Utc>,
+}
+
+/// A takeback waiting for the opponent's answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TakebackRequest {
+    // Tells a stale timeout apart from a newer request
+    pub id: String,
+    pub player_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameError {
+    NotInProgress,
+    NotYourTurn,
+    InvalidMove,
+    InvalidResumeToken,
+    NotFound(String),
+    Full(String),
+    AlreadyFinished(String),
+    NoOpenGames,
+    EmptyChat,
+    ChatTooLong,
+    UnprintableChat,
+    ChatRateLimited,
+    TooManyGames,
+    ShuttingDown,
+    NoReplay(String),
+    TakebacksDisabled,
+    TakebackAfterGameOver,
+    NothingToTakeBack,
+    TakebacksUsed(usize),
+    TakebackPending,
+    NoTakebackRequest,
+    RematchPending,
+    MatchOver,
+    NotRated(String),
+}
+
+impl GameError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            GameError::NotInProgress => ErrorCode::GameNotInProgress,
+            GameError::NotYourTurn => ErrorCode::NotYourTurn,
+            GameError::InvalidMove => ErrorCode::InvalidMove,
+            GameError::InvalidResumeToken => ErrorCode::InvalidResumeToken,
+            GameError::NotFound(_) => ErrorCode::GameNotFound,
+            GameError::Full(_) => ErrorCode::GameFull,
+            GameError::AlreadyFinished(_) => ErrorCode::GameFinished,
+            GameError::NoOpenGames => ErrorCode::NoOpenGames,
+            GameError::EmptyChat | GameError::UnprintableChat => ErrorCode::InvalidInput,
+            GameError::ChatTooLong => ErrorCode::ChatTooLong,
+            GameError::ChatRateLimited => ErrorCode::ChatRateLimited,
+            GameError::TooManyGames | GameError::ShuttingDown => ErrorCode::ServerBusy,
+            GameError::NoReplay(_) => ErrorCode::NoReplay,
+            GameError::TakebacksDisabled
+            | GameError::TakebackAfterGameOver
+            | GameError::NothingToTakeBack
+            | GameError::TakebacksUsed(_) => ErrorCode::TakebackNotAllowed,
+            GameError::TakebackPending => ErrorCode::TakebackPending,
+            GameError::NoTakebackRequest => ErrorCode::NoTakebackRequest,
+            GameError::RematchPending => ErrorCode::RematchPending,
+            GameError::MatchOver => ErrorCode::MatchOver,
+            GameError::NotRated(_) => ErrorCode::PlayerNotFound,
+        }
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameError::NotInProgress => write!(f, "Game is not in progress"),
+            GameError::NotYourTurn => write!(f, "Not your turn"),
+            GameError::InvalidMove => write!(f, "Invalid move"),
+            GameError::InvalidResumeToken => {
+                write!(
+                    f,
+                    "No game is holding a seat for you with that resume token"
+                )
+            }
+            GameError::NotFound(id) => write!(f, "Game {} not found", id),
+            GameError::Full(id) => write!(f, "Game {} is full", id),
+            GameError::AlreadyFinished(id) => write!(f, "Game {} has already finished", id),
+            GameError::NoOpenGames => write!(
+                f,
+                "Couldn't find any games available, please try again or create a new one"
+            ),
+            GameError::EmptyChat => write!(f, "Chat messages can't be empty"),
+            GameError::UnprintableChat => write!(
+                f,
+                "Chat messages can't contain line breaks, escape sequences or invisible characters"
+            ),
+            GameError::ChatTooLong => write!(
+                f,
+                "Chat messages can be at most {} characters",
+                CHAT_MAX_LENGTH
+            ),
+            GameError::ChatRateLimited => {
+                write!(f, "You're sending messages too quickly, wait a few seconds")
+            }
+            GameError::TooManyGames => write!(
+                f,
+                "The server is running as many games as it can, please try again later"
+            ),
+            GameError::ShuttingDown => {
+                write!(f, "The server is shutting down, so no new games can start")
+            }
+            GameError::NoReplay(id) => {
+                write!(f, "Game {} has no finished round to replay yet", id)
+            }
+            GameError::TakebacksDisabled => write!(f, "Takebacks are turned off on this server"),
+            GameError::TakebackAfterGameOver => {
+                write!(f, "Moves can't be taken back once the game is over")
+            }
+            GameError::NothingToTakeBack => write!(f, "You have no move to take back"),
+            GameError::TakebacksUsed(max) => {
+                write!(f, "You've already used your {} takebacks this game", max)
+            }
+            GameError::TakebackPending => {
+                write!(f, "A takeback request is already waiting for an answer")
+            }
+            GameError::NoTakebackRequest => {
+                write!(
+                    f,
+                    "There is no takeback request from your opponent to answer"
+                )
+            }
+            GameError::RematchPending => {
+                write!(f, "Waiting for your opponent to agree to the rematch")
+            }
+            GameError::MatchOver => write!(f, "The match is over, no more rounds are played"),
+            GameError::NotRated(name) => write!(f, "{} hasn't played a rated game yet", name),
+        }
+    }
+}
+
+impl From<GameError> for GameMessage {
+    fn from(error: GameError) -> Self {
+        GameMessage::Error(error.code(), error.to_string())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Game {
     pub id: String,
     pub board: Vec<Option<PlayerSymbol>>,
+    pub config: BoardConfig,
     pub players: Vec<Player>,
+    // Read-only watchers; they receive every broadcast but never take a turn
+    pub spectators: Vec<Player>,
     pub current_turn: usize,
     pub status: GameStatus,
+    // Shown in the lobby; the creator may have left since
+    pub creator: String,
+    pub created_at: Instant,
+    pub chat: VecDeque<ChatMessage>,
+    // Moves of the current round, oldest first
+    pub moves: Vec<MoveRecord>,
+    pub takeback: Option<TakebackRequest>,
+    // Takebacks each player has had accepted this round
+    pub takebacks: HashMap<PlayerSymbol, usize>,
+    // The series of rounds played by the same two players; wins are kept on each player
+    pub round: usize,
+    pub draws: usize,
+    // Seat that moves first this round
+    pub first_turn: usize,
+    // Ends the series once decided, e.g. `Some(3)` for best of three
+    pub best_of: Option<usize>,
+    // The player who asked for a rematch, waiting for the other to agree
+    pub rematch_offer: Option<String>,
+    pub clock: Clock,
+    // Set when the player to move ran out of time, to the symbol of the other player
+    pub won_on_time: Option<PlayerSymbol>,
 }
 
 impl Game {
-    pub fn new(player: Player) -> Self {
-        let game_id = crate::utils::generate_id();
+    pub fn new(player: Player, config: BoardConfig) -> Self {
+        let mut game = Game::with_id(crate::utils::generate_id(), player.get_name(), config);
+        game.add_player(player);
+        game
+    }
+
+    /// An empty game, e.g. one being rebuilt from storage.
+    pub fn with_id(id: String, creator: String, config: BoardConfig) -> Self {
         Game {
-            id: game_id,
-            board: vec![None; 9],
-            players: vec![player],
+            id,
+            board: vec![None; config.cells()],
+            config,
+            players: Vec::new(),
+            spectators: Vec::new(),
             current_turn: 0,
             status: GameStatus::WaitingForPlayers,
+            creator,
+            created_at: Instant::now(),
+            chat: VecDeque::new(),
+            moves: Vec::new(),
+            takeback: None,
+            takebacks: HashMap::new(),
+            round: 1,
+            draws: 0,
+            first_turn: 0,
+            best_of: None,
+            rematch_offer: None,
+            clock: Clock::new(TimeControl::default()),
+            won_on_time: None,
         }
     }
 
@@ -52,17 +317,38 @@ impl Game {
         self.players.push(player);
     }
 
+    pub fn get_player_mut(&mut self, player_id: &str) -> Option<&mut Player> {
+        self.players.iter_mut().find(|p| p.get_id() == player_id)
+    }
+
+    pub fn add_spectator(&mut self, spectator: Player) {
+        self.spectators.push(spectator);
+    }
+
+    pub fn remove_spectator(&mut self, spectator_id: &str) {
+        self.spectators.retain(|s| s.get_id() != spectator_id);
+    }
+
+    pub fn spectator_count(&self) -> usize {
+        self.spectators.len()
+    }
+
     pub fn get_current_turn_player(&self) -> Option<String> {
-        if let Some(player) = self.players.get(self.current_turn) {
-            Some(player.get_id())
-        } else {
-            None
-        }
+        self.players
+            .get(self.current_turn)
+            .map(|player| player.get_id())
     }
 
     pub fn reset(&mut self) {
-        self.board = vec![None; 9];
-        self.current_turn = 0;
+        self.board = vec![None; self.config.cells()];
+        self.current_turn = self.first_turn;
+        self.rematch_offer = None;
+        // The finished round stays in the game store for replays
+        self.moves.clear();
+        self.takeback = None;
+        self.takebacks.clear();
+        self.won_on_time = None;
+        self.clock.reset();
         if self.players.len() == 2 {
             self.status = GameStatus::InProgress;
         } else {
@@ -70,160 +356,385 @@ impl Game {
         }
     }
 
-    pub fn make_move(&mut self, player_id: &str, position: usize) -> Result<String, String> {
-        if self.status != GameStatus::InProgress {
-            return Err("Game is not in progress".to_string());
+    /// Starts the next round of the series: the players swap symbols and whoever moved
+    /// second last round moves first.
+    pub fn rematch(&mut self) {
+        if let [first, second] = self.players.as_mut_slice() {
+            let symbol = first.get_symbol();
+            first.set_symbol(second.get_symbol());
+            second.set_symbol(symbol);
         }
+        self.first_turn = (self.first_turn + 1) % 2;
+        self.round += 1;
+        self.reset();
+    }
 
-        let current_player = &self.players[self.current_turn];
-        if current_player.get_id() != player_id {
-            return Err("Not your turn".to_string());
+    /// Starts the series over, e.g. when a player leaves and someone new takes the seat.
+    pub fn reset_series(&mut self) {
+        for player in self.players.iter_mut() {
+            player.clear_wins();
         }
+        self.round = 1;
+        self.draws = 0;
+        self.first_turn = 0;
+    }
+
+    /// Whether a best-of match has been decided: a player has won a majority of its
+    /// rounds, or every round has been played.
+    pub fn match_over(&self) -> bool {
+        let best_of = match self.best_of {
+            Some(best_of) => best_of,
+            None => return false,
+        };
+        let most_wins = self.players.iter().map(|p| p.get_wins()).max();
+        self.status == GameStatus::Finished
+            && (most_wins.unwrap_or(0) > best_of / 2 || self.round >= best_of)
+    }
 
-        if position >= 9 || self.board[position].is_some() {
-            return Err("Invalid move".to_string());
+    /// The player ahead in the series, or `None` while it's level.
+    pub fn series_leader(&self) -> Option<&Player> {
+        match self.players.as_slice() {
+            [first, second] if first.get_wins() > second.get_wins() => Some(first),
+            [first, second] if second.get_wins() > first.get_wins() => Some(second),
+            _ => None,
         }
+    }
 
-        self.board[position] = Some(current_player.get_symbol());
-        self.current_turn = (self.current_turn + 1) % 2;
+    /// Validates a chat message from a seated player and adds it to the game's recent history.
+    pub fn chat(&mut self, player_id: &str, text: &str) -> Result<ChatMessage, GameError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(GameError::EmptyChat);
+        }
+        if text.chars().count() > CHAT_MAX_LENGTH {
+            return Err(GameError::ChatTooLong);
+        }
+        // Text clients print chat as it is, so it mustn't be able to fake server lines
+        if text.chars().any(is_unprintable) {
+            return Err(GameError::UnprintableChat);
+        }
+        let player = self
+            .get_player_mut(player_id)
+            .ok_or(GameError::NotInProgress)?;
+        if !player.allow_chat(CHAT_RATE_LIMIT, CHAT_RATE_WINDOW) {
+            return Err(GameError::ChatRateLimited);
+        }
 
-        Ok(self.get_game_state())
+        let message = ChatMessage {
+            name: player.get_name(),
+            text: text.to_string(),
+            sent_at: Utc::now(),
+        };
+        if self.chat.len() == CHAT_HISTORY {
+            self.chat.pop_front();
+        }
+        self.chat.push_back(message.clone());
+        Ok(message)
     }
 
-    pub fn get_game_state(&mut self) -> String {
-        let mut board_state = String::new();
+    pub fn chat_history(&self) -> Vec<ChatMessage> {
+        self.chat.iter().cloned().collect()
+    }
 
-        // Define ANSI color codes
-        let reset = "\x1b[0m"; // Reset to default
-        let light_gray = "\x1b[90m"; // Light gray for numbers
-        let bold_red = "\x1b[1;31m"; // Bold red for 'X'
-        let bold_blue = "\x1b[1;34m"; // Bold blue for 'O'
-        let dark_line = "\x1b[1;30m═══╬═══╬═══\x1b[0m\n"; // Dark line separator
+    pub fn make_move(&mut self, player_id: &str, position: usize) -> Result<GameView, GameError> {
+        if self.status != GameStatus::InProgress {
+            return Err(GameError::NotInProgress);
+        }
 
-        // Build the 3x3 grid
-        let mut some_count = 0;
-        for row in 0..3 {
-            for col in 0..3 {
-                let index = row * 3 + col;
-                if self.board[index].is_some() {
-                    some_count += 1;
-                }
-                let symbol = match self.board[index] {
-                    Some(PlayerSymbol::X) => format!("{} X {}", bold_red, reset),
-                    Some(PlayerSymbol::O) => format!("{} O {}", bold_blue, reset),
-                    None => format!("{} {} {}", light_gray, index + 1, reset), // Light gray for numbers
-                };
-                board_state.push_str(&symbol);
-                if col < 2 {
-                    board_state.push('║'); // Vertical separator
-                }
-            }
-            board_state.push('\n'); // Move to the next row
-            if row < 2 {
-                board_state.push_str(dark_line); // Dark separator
-            }
+        let current_player = &self.players[self.current_turn];
+        if current_player.get_id() != player_id {
+            return Err(GameError::NotYourTurn);
         }
-        if some_count == self.board.len() {
-            self.status = GameStatus::Finished;
-            return format!(
-                "🎮 Game ID: {}\n\n{}\n🌟 Result: Draw!! 🎉",
-                self.id, board_state
-            );
-        }
-        let current_player = &self.players[self.current_turn].get_name();
-        let current_symbol = match &self.players[self.current_turn].get_symbol() {
-            PlayerSymbol::X => 'X',
-            PlayerSymbol::O => 'O',
+
+        if position >= self.board.len() || self.board[position].is_some() {
+            return Err(GameError::InvalidMove);
+        }
+
+        let record = MoveRecord {
+            player: current_player.get_name(),
+            symbol: current_player.get_symbol(),
+            cell: position,
+            at: Utc::now(),
         };
-        match self.check_winner() {
-            None => {
-                format!(
-                    "🎮 Game ID: {}\n\n{}\n🌟 It's {} ({})'s turn! (Enter a number from 1 to 9)",
-                    self.id, board_state, current_player, current_symbol
-                )
-            }
-            Some(PlayerSymbol::X) => {
+        self.replay_move(record);
+        self.clock.moved();
+        if self.status == GameStatus::Finished {
+            self.clock.stop();
+        }
+        Ok(self.view())
+    }
+
+    /// Plays a move that is already known to be legal, e.g. one read back from storage.
+    pub fn replay_move(&mut self, record: MoveRecord) {
+        if let Some(cell) = self.board.get_mut(record.cell) {
+            *cell = Some(record.symbol.clone());
+            self.current_turn = (self.current_turn + 1) % 2;
+            self.moves.push(record);
+        }
+        match self.outcome() {
+            GameOutcome::Ongoing | GameOutcome::WonOnTime { .. } => {}
+            GameOutcome::Win { symbol, .. } => {
                 self.status = GameStatus::Finished;
-                let name = match self.players[0].get_symbol() {
-                    PlayerSymbol::X => self.players[0].get_name(),
-                    PlayerSymbol::O => self.players[1].get_name(),
-                };
-                format!(
-                    "🎮 Game ID: {}\n\n{}\n🏆 Winner: {} (X) 🎉",
-                    self.id, board_state, name
-                )
+                if let Some(player) = self.players.iter_mut().find(|p| p.get_symbol() == symbol) {
+                    player.add_win();
+                }
             }
-            Some(PlayerSymbol::O) => {
+            GameOutcome::Draw => {
                 self.status = GameStatus::Finished;
-                let name = match self.players[1].get_symbol() {
-                    PlayerSymbol::O => self.players[1].get_name(),
-                    PlayerSymbol::X => self.players[0].get_name(),
-                };
-                format!(
-                    "🎮 Game ID: {}\n\n{}\n🏆 Winner: {} (O) 🎉",
-                    self.id, board_state, name
-                )
+                self.draws += 1;
             }
         }
     }
 
-    fn check_winner(&self) -> Option<PlayerSymbol> {
-        // Check rows and columns
-        for i in 0..3 {
-            // Rows
-            if let Some(symbol) = self.board[i * 3].clone() {
-                if self.board[i * 3 + 1] == Some(symbol.clone())
-                    && self.board[i * 3 + 2] == Some(symbol.clone())
-                {
-                    return Some(symbol.clone());
-                }
-            }
-            // Columns
-            if let Some(symbol) = self.board[i].clone() {
-                // i+3*1 && i+3*2
-                if self.board[i + 3] == Some(symbol.clone())
-                    && self.board[i + 6] == Some(symbol.clone())
-                {
-                    return Some(symbol.clone());
-                }
-            }
+    /// Asks for the player's last move back, within the game's takeback limits. The request
+    /// waits for the opponent's answer.
+    pub fn request_takeback(
+        &mut self,
+        player_id: &str,
+        max_takebacks: usize,
+    ) -> Result<TakebackRequest, GameError> {
+        match self.status {
+            GameStatus::Finished => return Err(GameError::TakebackAfterGameOver),
+            GameStatus::WaitingForPlayers => return Err(GameError::NotInProgress),
+            GameStatus::InProgress => {}
+        }
+        if max_takebacks == 0 {
+            return Err(GameError::TakebacksDisabled);
+        }
+        if self.takeback.is_some() {
+            return Err(GameError::TakebackPending);
+        }
+        let symbol = self
+            .players
+            .iter()
+            .find(|p| p.get_id() == player_id)
+            .map(|p| p.get_symbol())
+            .ok_or(GameError::NotInProgress)?;
+        if !self.moves.iter().any(|record| record.symbol == symbol) {
+            return Err(GameError::NothingToTakeBack);
+        }
+        if self.takebacks.get(&symbol).copied().unwrap_or(0) >= max_takebacks {
+            return Err(GameError::TakebacksUsed(max_takebacks));
         }
 
-        // Check diagonals
-        if let Some(symbol) = self.board[0].clone() {
-            // 0+3*1+1 && 0+3*2+2
-            if self.board[4] == Some(symbol.clone()) && self.board[8] == Some(symbol.clone()) {
-                return Some(symbol.clone());
-            }
+        let request = TakebackRequest {
+            id: crate::utils::generate_id(),
+            player_id: player_id.to_string(),
+        };
+        self.takeback = Some(request.clone());
+        Ok(request)
+    }
+
+    /// Undoes moves back to and including the last one `symbol` played, so it is that
+    /// player's turn again. Returns how many moves were undone.
+    pub fn take_back(&mut self, symbol: &PlayerSymbol) -> usize {
+        self.takeback = None;
+        if !self.moves.iter().any(|record| &record.symbol == symbol) {
+            return 0;
         }
-        if let Some(symbol) = self.board[2].clone() {
-            // 2+3*1-1 && 2+3*2-2
-            if self.board[4] == Some(symbol.clone()) && self.board[6] == Some(symbol.clone()) {
-                return Some(symbol.clone());
+        let mut undone = 0;
+        while let Some(record) = self.moves.pop() {
+            self.board[record.cell] = None;
+            undone += 1;
+            if &record.symbol == symbol {
+                break;
             }
         }
+        if let Some(turn) = self.players.iter().position(|p| &p.get_symbol() == symbol) {
+            self.current_turn = turn;
+        }
+        *self.takebacks.entry(symbol.clone()).or_insert(0) += 1;
+        undone
+    }
 
-        None
+    /// Ends the round with a loss for `symbol`, the player who ran out of time.
+    pub fn lose_on_time(&mut self, symbol: &PlayerSymbol) {
+        let winner = match self.players.iter_mut().find(|p| &p.get_symbol() != symbol) {
+            Some(winner) => winner,
+            None => return,
+        };
+        winner.add_win();
+        self.won_on_time = Some(winner.get_symbol());
+        self.status = GameStatus::Finished;
+        self.takeback = None;
+        // Paused rather than stopped, so the loser's clock still reads zero
+        self.clock.pause();
     }
 
-    pub async fn broadcast_to_players(&self, message: String) {
-        for player in self.players.clone() {
-            let ws_sink = player.get_ws_sink();
-            let mut player_ws = ws_sink.lock().await;
-            player_ws
-                .send(Message::Text(format!("📢 {}\n", message).into()))
-                .await
-                .unwrap();
+    /// Decides the result of the game from the board, unless a player lost on time.
+    pub fn outcome(&self) -> GameOutcome {
+        if let Some(symbol) = &self.won_on_time {
+            return GameOutcome::WonOnTime {
+                symbol: symbol.clone(),
+            };
+        }
+        if let Some((symbol, line)) = self.config.find_winning_line(&self.board) {
+            return GameOutcome::Win { symbol, line };
         }
+        if self.board.iter().all(|cell| cell.is_some()) {
+            return GameOutcome::Draw;
+        }
+        GameOutcome::Ongoing
+    }
+
+    pub fn current_player(&self) -> Option<&Player> {
+        self.players.get(self.current_turn)
+    }
+
+    pub fn player_with_symbol(&self, symbol: &PlayerSymbol) -> Option<&Player> {
+        self.players.iter().find(|p| &p.get_symbol() == symbol)
+    }
+
+    /// Renders the board for text clients and captures the matching snapshot for JSON clients.
+    pub fn view(&self) -> GameView {
+        GameView {
+            text: AnsiRenderer.render(self),
+            snapshot: JsonRenderer::snapshot(self),
+        }
+    }
+
+    pub fn broadcast_to_players(&self, message: GameMessage) {
+        for player in self.players.iter().chain(self.spectators.iter()) {
+            player.notify(&message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Difficulty;
+
+    // A classic game in progress between two seats, returned with their ids
+    fn started() -> (Game, String, String) {
+        let x = Player::bot(Difficulty::Random, PlayerSymbol::X);
+        let o = Player::bot(Difficulty::Random, PlayerSymbol::O);
+        let (x_id, o_id) = (x.get_id(), o.get_id());
+        let mut game = Game::new(x, BoardConfig::default());
+        game.add_player(o);
+        game.set_status(GameStatus::InProgress);
+        (game, x_id, o_id)
+    }
+
+    #[test]
+    fn chat_is_trimmed_and_kept_for_later() {
+        let (mut game, x, _) = started();
+        let message = game.chat(&x, "  good luck  ").unwrap();
+        assert_eq!(message.text, "good luck");
+        assert_eq!(game.chat_history().len(), 1);
+    }
+
+    #[test]
+    fn chat_turns_down_empty_long_and_unprintable_messages() {
+        let (mut game, x, _) = started();
+        assert!(matches!(game.chat(&x, "   "), Err(GameError::EmptyChat)));
+        let long = "a".repeat(CHAT_MAX_LENGTH + 1);
+        assert!(matches!(game.chat(&x, &long), Err(GameError::ChatTooLong)));
+        for text in ["hi\n📢 You win!", "\u{1b}[2Jgg", "g\u{200B}g", "\u{202E}gg"] {
+            assert!(matches!(
+                game.chat(&x, text),
+                Err(GameError::UnprintableChat)
+            ));
+        }
+        assert!(game.chat_history().is_empty());
+    }
+
+    #[test]
+    fn chat_is_rate_limited() {
+        let (mut game, x, o) = started();
+        for _ in 0..CHAT_RATE_LIMIT {
+            game.chat(&x, "hi").unwrap();
+        }
+        assert!(matches!(
+            game.chat(&x, "hi"),
+            Err(GameError::ChatRateLimited)
+        ));
+        assert!(game.chat(&o, "hi").is_ok());
+    }
+
+    #[test]
+    fn takeback_undoes_back_to_the_players_last_move() {
+        let (mut game, x, o) = started();
+        game.make_move(&x, 0).unwrap();
+        game.make_move(&o, 4).unwrap();
+        let request = game.request_takeback(&x, 1).unwrap();
+        assert_eq!(request.player_id, x);
+        assert!(matches!(
+            game.request_takeback(&o, 1),
+            Err(GameError::TakebackPending)
+        ));
+        assert_eq!(game.take_back(&PlayerSymbol::X), 2);
+        assert!(game.board.iter().all(Option::is_none));
+        assert_eq!(game.get_current_turn_player(), Some(x));
+    }
+
+    #[test]
+    fn takebacks_are_limited() {
+        let (mut game, x, o) = started();
+        assert!(matches!(
+            game.request_takeback(&x, 0),
+            Err(GameError::TakebacksDisabled)
+        ));
+        assert!(matches!(
+            game.request_takeback(&x, 1),
+            Err(GameError::NothingToTakeBack)
+        ));
+        game.make_move(&x, 0).unwrap();
+        game.request_takeback(&x, 1).unwrap();
+        game.take_back(&PlayerSymbol::X);
+        game.make_move(&x, 0).unwrap();
+        assert!(matches!(
+            game.request_takeback(&x, 1),
+            Err(GameError::TakebacksUsed(1))
+        ));
+        // X takes the top row
+        for (id, cell) in [(&o, 3), (&x, 1), (&o, 4), (&x, 2)] {
+            game.make_move(id, cell).unwrap();
+        }
+        assert!(matches!(
+            game.request_takeback(&o, 1),
+            Err(GameError::TakebackAfterGameOver)
+        ));
     }
 
-    // async fn broadcast_close_players(&self) {
-    //     for player in self.players.clone() {
-    //         let mut player_ws = player.ws_sink.lock().await;
-    //         player_ws
-    //             .send(Message::Close(None))
-    //             .await
-    //             .unwrap();
-    //     }
-    // }
+    // `first` takes the top row while `second` plays the middle one
+    fn win_round(game: &mut Game, first: &str, second: &str) {
+        for (id, cell) in [(first, 0), (second, 3), (first, 1), (second, 4), (first, 2)] {
+            game.make_move(id, cell).unwrap();
+        }
+    }
+
+    #[test]
+    fn rematch_swaps_symbols_and_who_moves_first() {
+        let (mut game, x, o) = started();
+        win_round(&mut game, &x, &o);
+        assert_eq!(game.series_leader().map(Player::get_id), Some(x.clone()));
+
+        game.rematch();
+        assert_eq!(game.get_status(), GameStatus::InProgress);
+        assert_eq!(game.round, 2);
+        assert_eq!(game.get_current_turn_player(), Some(o.clone()));
+        assert_eq!(game.current_player().unwrap().get_symbol(), PlayerSymbol::X);
+        assert!(game.board.iter().all(Option::is_none));
+        // The series score carries over
+        assert_eq!(game.series_leader().map(Player::get_id), Some(x));
+    }
+
+    #[test]
+    fn best_of_ends_once_a_player_has_a_majority() {
+        let (mut game, x, o) = started();
+        game.best_of = Some(3);
+        win_round(&mut game, &x, &o);
+        assert!(!game.match_over());
+        game.rematch();
+        win_round(&mut game, &o, &x);
+        assert!(!game.match_over());
+        game.rematch();
+        win_round(&mut game, &x, &o);
+        assert!(game.match_over());
+
+        game.reset_series();
+        assert_eq!(game.round, 1);
+        assert!(game.series_leader().is_none());
+    }
 }
@@ -0,0 +1,199 @@
+// http.rs
+
+use crate::config::ServerConfig;
+use crate::game::GameError;
+use crate::protocol::ErrorCode;
+use crate::server::GameServer;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::{error, info};
+use prometheus::TEXT_FORMAT;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+// Longest announcement `POST /broadcast` accepts
+const MAX_ANNOUNCEMENT: usize = 500;
+
+/// The HTTP apps to serve and the address each listens on. `/metrics` and the admin API
+/// share a listener when they are given the same port.
+pub fn apps(config: &ServerConfig, server: Arc<GameServer>) -> Vec<(SocketAddr, Router)> {
+    let mut apps: Vec<(SocketAddr, Router)> = Vec::new();
+    if let Some(addr) = config.metrics_addr() {
+        apps.push((addr, metrics_routes(server.clone())));
+    }
+    if let (Some(addr), Some(token)) = (config.admin_addr(), config.admin_token.clone()) {
+        let admin = admin_routes(server, token);
+        match apps.iter_mut().find(|(existing, _)| *existing == addr) {
+            Some((_, app)) => *app = std::mem::take(app).merge(admin),
+            None => apps.push((addr, admin)),
+        }
+    }
+    apps
+}
+
+/// Serves `app` on its own listener, apart from the game's WebSocket port.
+pub fn spawn(listener: TcpListener, app: Router) {
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("The HTTP server stopped: {}", e);
+        }
+    });
+}
+
+fn metrics_routes(server: Arc<GameServer>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(server)
+}
+
+fn admin_routes(server: Arc<GameServer>, token: String) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/games", get(list_games))
+        .route("/games/{id}", get(get_game).delete(close_game))
+        .route("/broadcast", post(broadcast))
+        .route_layer(middleware::from_fn_with_state(Arc::new(token), authorize))
+        .with_state(server)
+}
+
+async fn metrics(State(server): State<Arc<GameServer>>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, TEXT_FORMAT)], server.export_metrics())
+}
+
+// Lets through only requests carrying `Authorization: Bearer <admin token>`
+async fn authorize(State(token): State<Arc<String>>, request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if same_token(given.trim(), &token) => next.run(request).await,
+        _ => ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "A valid admin token is required",
+        )
+        .into_response(),
+    }
+}
+
+// Compares in time that doesn't depend on where the tokens first differ
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn readyz(State(server): State<Arc<GameServer>>) -> (StatusCode, &'static str) {
+    if server.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
+
+async fn list_games(State(server): State<Arc<GameServer>>) -> impl IntoResponse {
+    Json(server.list_games())
+}
+
+async fn get_game(
+    State(server): State<Arc<GameServer>>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(server.inspect_game(&id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct CloseParams {
+    reason: Option<String>,
+}
+
+async fn close_game(
+    State(server): State<Arc<GameServer>>,
+    Path(id): Path<String>,
+    Query(params): Query<CloseParams>,
+) -> Result<StatusCode, ApiError> {
+    let reason = params
+        .reason
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty());
+    server.close_game(&id, reason).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct Announcement {
+    message: String,
+}
+
+async fn broadcast(
+    State(server): State<Arc<GameServer>>,
+    Json(announcement): Json<Announcement>,
+) -> Result<impl IntoResponse, ApiError> {
+    let message = announcement.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_ANNOUNCEMENT {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidInput.name(),
+            format!(
+                "Announcements must be 1 to {} characters long",
+                MAX_ANNOUNCEMENT
+            ),
+        ));
+    }
+    let recipients = server.announce(message);
+    info!("Announced to {} connections: {}", recipients, message);
+    Ok(Json(json!({ "recipients": recipients })))
+}
+
+// Errors go back as `{"code": ..., "message": ...}`, with the codes JSON clients see
+#[derive(Debug, Serialize)]
+struct ApiError {
+    #[serde(skip)]
+    status: StatusCode,
+    code: String,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: impl Into<String>, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<GameError> for ApiError {
+    fn from(error: GameError) -> Self {
+        let status = match error.code() {
+            ErrorCode::GameNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::CONFLICT,
+        };
+        ApiError::new(status, error.code().name(), error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(&self)).into_response()
+    }
+}
@@ -0,0 +1,265 @@
+// limits.rs
+
+use crate::player::WsStream;
+use dashmap::DashMap;
+use futures::stream::SplitStream;
+use futures::{Stream, StreamExt};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio_tungstenite::tungstenite::error::CapacityError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// A limit a client ran into, and how its connection is closed because of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    ServerFull,
+    TooManyFromAddress,
+    RateLimited,
+    MessageTooBig,
+}
+
+impl Limit {
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            // 1013: try again later
+            Limit::ServerFull | Limit::TooManyFromAddress => CloseCode::Again,
+            // 1008: policy violation
+            Limit::RateLimited => CloseCode::Policy,
+            // 1009: message too big
+            Limit::MessageTooBig => CloseCode::Size,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Limit::ServerFull => "The server is full, please try again later",
+            Limit::TooManyFromAddress => "Too many connections from your address",
+            Limit::RateLimited => "Too many messages, slow down",
+            Limit::MessageTooBig => "Message too big",
+        }
+    }
+
+    /// The `limit` label of `tictactoe_limit_hits_total`.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Limit::ServerFull => "server_full",
+            Limit::TooManyFromAddress => "per_ip",
+            Limit::RateLimited => "rate",
+            Limit::MessageTooBig => "message_size",
+        }
+    }
+}
+
+/// Counts open connections, in total and per client address. Connections hold a
+/// `Slot` from before their handshake until they close.
+#[derive(Debug)]
+pub struct Connections {
+    max_total: usize,
+    max_per_ip: usize,
+    total: AtomicUsize,
+    per_ip: DashMap<IpAddr, usize>,
+}
+
+impl Connections {
+    pub fn new(max_total: usize, max_per_ip: usize) -> Self {
+        Connections {
+            max_total,
+            max_per_ip,
+            total: AtomicUsize::new(0),
+            per_ip: DashMap::new(),
+        }
+    }
+
+    /// Takes a slot for a connection from `ip` unless that would go over a limit.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<Slot, Limit> {
+        let admitted = {
+            // The address's entry stays locked while both counts are checked and taken
+            let mut count = self.per_ip.entry(ip).or_insert(0);
+            if *count >= self.max_per_ip {
+                Err(Limit::TooManyFromAddress)
+            } else if self
+                .total
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                    (total < self.max_total).then_some(total + 1)
+                })
+                .is_err()
+            {
+                Err(Limit::ServerFull)
+            } else {
+                *count += 1;
+                Ok(())
+            }
+        };
+        match admitted {
+            Ok(()) => Ok(Slot {
+                connections: self.clone(),
+                ip,
+            }),
+            Err(limit) => {
+                self.per_ip.remove_if(&ip, |_, count| *count == 0);
+                Err(limit)
+            }
+        }
+    }
+
+    fn release(&self, ip: IpAddr) {
+        self.total.fetch_sub(1, Ordering::SeqCst);
+        self.per_ip.remove_if_mut(&ip, |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+}
+
+/// A connection's place under the limits, given back when dropped.
+#[derive(Debug)]
+pub struct Slot {
+    connections: Arc<Connections>,
+    ip: IpAddr,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.connections.release(self.ip);
+    }
+}
+
+/// Lets through `rate` messages a second on average, and bursts of up to `burst`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        TokenBucket {
+            rate: f64::from(rate),
+            burst: f64::from(burst),
+            tokens: f64::from(burst),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// The read half of a client's socket, metered by a token bucket. A client that sends
+/// too fast, or a message over the size cap, sees its stream end, and `limit` says why.
+pub struct Inbound {
+    stream: SplitStream<WsStream>,
+    bucket: TokenBucket,
+    limit: Option<Limit>,
+}
+
+impl Inbound {
+    pub fn new(stream: SplitStream<WsStream>, bucket: TokenBucket) -> Self {
+        Inbound {
+            stream,
+            bucket,
+            limit: None,
+        }
+    }
+
+    pub fn limit(&self) -> Option<Limit> {
+        self.limit
+    }
+}
+
+impl Stream for Inbound {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.limit.is_some() {
+            return Poll::Ready(None);
+        }
+        let item = match self.stream.poll_next_unpin(cx) {
+            Poll::Ready(item) => item,
+            Poll::Pending => return Poll::Pending,
+        };
+        let limit = match &item {
+            Some(Err(tungstenite::Error::Capacity(CapacityError::MessageTooLong { .. }))) => {
+                Some(Limit::MessageTooBig)
+            }
+            // Pings count too, so they can't be used to flood the server
+            Some(Ok(message)) if !message.is_close() && !self.bucket.try_take() => {
+                Some(Limit::RateLimited)
+            }
+            _ => None,
+        };
+        if limit.is_some() {
+            self.limit = limit;
+            return Poll::Ready(None);
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+    }
+
+    #[test]
+    fn bucket_lets_a_burst_through_then_stops() {
+        let mut bucket = TokenBucket::new(1, 3);
+        assert!((0..3).all(|_| bucket.try_take()));
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn bucket_refills_at_its_rate() {
+        let mut bucket = TokenBucket::new(100, 1);
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(bucket.try_take());
+        // Never more than the burst, however long it waited
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn connections_are_limited_per_address_and_in_total() {
+        let connections = Arc::new(Connections::new(2, 1));
+        let first = connections.admit(ip(1)).unwrap();
+        assert_eq!(
+            connections.admit(ip(1)).unwrap_err(),
+            Limit::TooManyFromAddress
+        );
+        let _second = connections.admit(ip(2)).unwrap();
+        assert_eq!(connections.admit(ip(3)).unwrap_err(), Limit::ServerFull);
+
+        drop(first);
+        assert!(connections.admit(ip(3)).is_ok());
+    }
+
+    #[test]
+    fn rejected_addresses_leave_no_entry_behind() {
+        let connections = Arc::new(Connections::new(1, 5));
+        let _only = connections.admit(ip(1)).unwrap();
+        assert!(connections.admit(ip(2)).is_err());
+        assert!(!connections.per_ip.contains_key(&ip(2)));
+    }
+}
@@ -1,41 +1,280 @@
 // main.rs
 
-use log::info;
+use log::{error, info, warn};
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
-use tokio_tungstenite::accept_async;
+use tokio::sync::Semaphore;
+use tokio::time::{sleep, timeout, Duration};
+mod accounts;
+mod actor;
+mod board;
+mod bot;
+mod clock;
+mod config;
 mod game;
+mod http;
+mod limits;
+mod metrics;
+mod names;
+mod outbox;
 mod player;
+mod protocol;
+mod rating;
+mod render;
+mod replay;
 mod server;
+mod storage;
+mod tls;
 mod utils;
 mod websocket;
+use accounts::Accounts;
+use config::ServerConfig;
+use names::NamePolicy;
+use player::Transport;
 use server::GameServer;
 use std::error::Error;
-use websocket::handle_client;
+use std::path::absolute;
+use storage::{FileStore, GameStore, MemoryStore};
+use websocket::{accept_connection, handle_client, reject_client};
+
+// How long sockets get to send their close frames once games are stopped
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a client gets to finish the TLS and WebSocket handshakes
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Connections over a limit that may be mid-handshake at once, to be told why they are
+// turned away; any more are dropped straight after they are accepted
+const MAX_REJECTING: usize = 16;
+// Pause after a failed accept, such as running out of file descriptors, before trying again
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
+    let config = match ServerConfig::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let tls_acceptor = match &config.tls {
+        Some(tls) => match tls::acceptor(tls) {
+            Ok(acceptor) => Some(Arc::new(acceptor)),
+            Err(e) => {
+                error!("Invalid configuration: {}", e);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+
+    let listener = TcpListener::bind(config.addr()).await?;
+
+    info!("WebSocket server listening on {}", config.url());
+
+    let store: Box<dyn GameStore> = match &config.store {
+        Some(path) => match FileStore::open(path) {
+            Ok(store) => {
+                info!(
+                    "Recording games to {}",
+                    absolute(path).unwrap_or_else(|_| path.clone()).display()
+                );
+                Box::new(store)
+            }
+            Err(e) => {
+                error!("Can't open the game store {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        None => Box::new(MemoryStore::default()),
+    };
+
+    let accounts = match &config.accounts {
+        Some(path) => match Accounts::open(path) {
+            Ok(accounts) => {
+                info!(
+                    "Keeping accounts in {}",
+                    absolute(path).unwrap_or_else(|_| path.clone()).display()
+                );
+                accounts
+            }
+            Err(e) => {
+                error!("Can't open the accounts file {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        None => Accounts::in_memory(),
+    };
+    if !accounts.is_empty() {
+        info!("Loaded {} accounts", accounts.len());
+    }
 
-    info!("WebSocket server listening on ws://127.0.0.1:8080");
+    let names = match &config.name_blocklist {
+        Some(path) => match NamePolicy::load(path) {
+            Ok(names) => names,
+            Err(e) => {
+                error!("Can't read the name blocklist {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        None => NamePolicy::default(),
+    };
+    if !names.is_empty() {
+        info!("Loaded {} blocked words for names", names.len());
+    }
 
-    let server = Arc::new(Mutex::new(GameServer::new()));
+    let server = Arc::new(GameServer::new(
+        config.as_ref().clone(),
+        store,
+        accounts,
+        names,
+    ));
+    match server.restore_games() {
+        Ok(0) => {}
+        Ok(count) => info!("Restored {} unfinished games", count),
+        Err(e) => {
+            error!("Can't restore games: {}", e);
+            std::process::exit(2);
+        }
+    }
     GameServer::start_logging_active_games(server.clone());
 
+    for (addr, app) in http::apps(&config, server.clone()) {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                info!("HTTP server listening on http://{}", addr);
+                http::spawn(listener, app);
+            }
+            Err(e) => {
+                error!("Can't serve HTTP on {}: {}", addr, e);
+                std::process::exit(2);
+            }
+        }
+    }
+    server.set_ready(true);
+
+    let rejecting = Arc::new(Semaphore::new(MAX_REJECTING));
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        // Counted from before the handshake, so unfinished handshakes can't pile up
+        let admitted = server.connections().admit(peer.ip());
+        let rejection = match &admitted {
+            Ok(_) => None,
+            Err(limit) => match rejecting.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    server.metrics().limit_hit(*limit);
+                    continue;
+                }
+            },
+        };
+        let server = server.clone();
+        let config = config.clone();
+        let tls_acceptor = tls_acceptor.clone();
 
-        match accept_async(stream).await {
-            Ok(ws_stream) => {
-                let server_clone = server.clone();
-                tokio::spawn(handle_client(ws_stream, server_clone));
+        // Handshakes run in the connection's own task so a slow client can't hold up the listener
+        tokio::spawn(async move {
+            let _rejection = rejection;
+            let handshake = async {
+                let stream: Box<dyn Transport> = match tls_acceptor {
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(tls_stream) => Box::new(tls_stream),
+                        Err(e) => {
+                            warn!("TLS handshake with {} failed: {}", peer, e);
+                            return None;
+                        }
+                    },
+                    None => Box::new(stream),
+                };
+                accept_connection(stream, &config).await.ok()
+            };
+            let (ws_stream, protocol) = match timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                Ok(Some(accepted)) => accepted,
+                _ => return,
+            };
+
+            // Clients over a limit are still told why before they are closed
+            let _slot = match admitted {
+                Ok(slot) => slot,
+                Err(limit) => {
+                    warn!("Rejected connection from {}: {}", peer, limit.reason());
+                    server.metrics().limit_hit(limit);
+                    reject_client(ws_stream, protocol, limit).await;
+                    return;
+                }
+            };
+            server.metrics().connections.inc();
+            handle_client(ws_stream, protocol, server.clone(), config).await;
+            server.metrics().connections.dec();
+        });
+    }
+
+    // Stop taking connections, let games finish, then close everyone's socket
+    drop(listener);
+    info!(
+        "Shutting down, giving games {} seconds to finish",
+        config.shutdown_grace.as_secs()
+    );
+    server.shutdown(config.shutdown_grace).await;
+    let flushed = timeout(CLOSE_TIMEOUT, async {
+        while server.metrics().connections.get() > 0 {
+            sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    if flushed.is_err() {
+        warn!(
+            "{} connections didn't close in time",
+            server.metrics().connections.get()
+        );
+    }
+    // Games record their last events as their players leave
+    let store = server.clone();
+    let _ = tokio::task::spawn_blocking(move || store.flush_store()).await;
+    info!("Server stopped");
+    Ok(())
+}
+
+// Resolves on Ctrl+C, or SIGTERM where there is one
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Can't listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
             }
-            Err(_) => {
-                continue;
+            Err(e) => {
+                error!("Can't listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
             }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
     }
 }
@@ -0,0 +1,176 @@
+// metrics.rs
+
+use crate::game::GameStatus;
+use crate::limits::Limit;
+use crate::player::PlayerSymbol;
+use crate::protocol::ErrorCode;
+use log::error;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+/// How a round ended, as counted by `tictactoe_game_outcomes_total`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    XWin,
+    OWin,
+    Draw,
+    // A player left, or never came back, in the middle of a round
+    Abandoned,
+}
+
+impl Outcome {
+    /// The round was won by `symbol`, on the board or on time.
+    pub fn won_by(symbol: &PlayerSymbol) -> Self {
+        match symbol {
+            PlayerSymbol::X => Outcome::XWin,
+            PlayerSymbol::O => Outcome::OWin,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::XWin => "x_win",
+            Outcome::OWin => "o_win",
+            Outcome::Draw => "draw",
+            Outcome::Abandoned => "abandoned",
+        }
+    }
+}
+
+/// The server's Prometheus metrics, served on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub connections: IntGauge,
+    games: IntGaugeVec,
+    pub moves: IntCounter,
+    outcomes: IntCounterVec,
+    pub inactivity_disconnects: IntCounter,
+    join_failures: IntCounterVec,
+    limit_hits: IntCounterVec,
+    // Time from queueing a broadcast for a connection to writing it to the socket
+    pub broadcast_latency: Histogram,
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let connections =
+            IntGauge::new("tictactoe_connections", "Clients connected").expect("valid metric");
+        let games = IntGaugeVec::new(
+            Opts::new("tictactoe_games", "Games that exist, by status"),
+            &["status"],
+        )
+        .expect("valid metric");
+        let moves = IntCounter::new("tictactoe_moves_total", "Moves played").expect("valid metric");
+        let outcomes = IntCounterVec::new(
+            Opts::new(
+                "tictactoe_game_outcomes_total",
+                "Rounds finished, by how they ended",
+            ),
+            &["outcome"],
+        )
+        .expect("valid metric");
+        let inactivity_disconnects = IntCounter::new(
+            "tictactoe_inactivity_disconnects_total",
+            "Players disconnected for staying silent too long",
+        )
+        .expect("valid metric");
+        let join_failures = IntCounterVec::new(
+            Opts::new(
+                "tictactoe_join_failures_total",
+                "Attempts to join a game that failed, by error code",
+            ),
+            &["reason"],
+        )
+        .expect("valid metric");
+        let limit_hits = IntCounterVec::new(
+            Opts::new(
+                "tictactoe_limit_hits_total",
+                "Connections refused or closed for going over a limit, by limit",
+            ),
+            &["limit"],
+        )
+        .expect("valid metric");
+        let broadcast_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "tictactoe_broadcast_latency_seconds",
+                "Time from queueing a broadcast for a client to writing it to the socket",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+            ]),
+        )
+        .expect("valid metric");
+
+        let registry = Registry::new();
+        let collectors: [Box<dyn prometheus::core::Collector>; 8] = [
+            Box::new(connections.clone()),
+            Box::new(games.clone()),
+            Box::new(moves.clone()),
+            Box::new(outcomes.clone()),
+            Box::new(inactivity_disconnects.clone()),
+            Box::new(join_failures.clone()),
+            Box::new(limit_hits.clone()),
+            Box::new(broadcast_latency.clone()),
+        ];
+        for collector in collectors {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Metrics {
+            registry,
+            connections,
+            games,
+            moves,
+            outcomes,
+            inactivity_disconnects,
+            join_failures,
+            limit_hits,
+            broadcast_latency,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn round_finished(&self, outcome: Outcome) {
+        self.outcomes.with_label_values(&[outcome.label()]).inc();
+    }
+
+    pub fn join_failed(&self, code: ErrorCode) {
+        self.join_failures.with_label_values(&[code.name()]).inc();
+    }
+
+    pub fn limit_hit(&self, limit: Limit) {
+        self.limit_hits.with_label_values(&[limit.label()]).inc();
+    }
+
+    /// Replaces the per-status game counts.
+    pub fn set_games(&self, counts: &[(GameStatus, usize)]) {
+        for (status, count) in counts {
+            let label = match status {
+                GameStatus::WaitingForPlayers => "waiting_for_players",
+                GameStatus::InProgress => "in_progress",
+                GameStatus::Finished => "finished",
+            };
+            self.games.with_label_values(&[label]).set(*count as i64);
+        }
+    }
+
+    /// Everything in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
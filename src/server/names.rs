@@ -0,0 +1,168 @@
+// names.rs
+
+use crate::accounts::AuthError;
+use crate::utils::is_unprintable;
+use std::io;
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest name a player may use, guest or registered.
+pub const MAX_NAME: usize = 20;
+
+/// What a player's name must look like. Names end up in broadcasts and on other players'
+/// boards, so they are kept to one short line of visible text.
+#[derive(Debug, Default)]
+pub struct NamePolicy {
+    // Folded the way names are before matching, see `fold`
+    blocklist: Vec<String>,
+}
+
+impl NamePolicy {
+    /// Reads the blocklist: a word per line, with blank lines and `#` comments skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let blocklist = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(fold)
+            .filter(|word| !word.is_empty())
+            .collect();
+        Ok(NamePolicy { blocklist })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocklist.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocklist.len()
+    }
+
+    /// A new name, as it will be shown: normalised, and turned down if it is on the blocklist.
+    pub fn check(&self, name: &str) -> Result<String, AuthError> {
+        let name = normalize(name)?;
+        let folded = fold(&name);
+        if self.blocklist.iter().any(|word| folded.contains(word)) {
+            return Err(AuthError::InvalidName(
+                "That name isn't allowed here, please pick another".to_string(),
+            ));
+        }
+        Ok(name)
+    }
+}
+
+/// Trims the name, puts it in Unicode normal form C and collapses runs of spaces, turning
+/// down names that are empty, too long, or carry control, escape or invisible characters.
+/// Names of existing accounts go through this too, so they are looked up the same way.
+pub fn normalize(name: &str) -> Result<String, AuthError> {
+    let name: String = name.trim().nfc().collect();
+    if name.is_empty() {
+        return Err(AuthError::InvalidName("Please enter a name".to_string()));
+    }
+    // Control characters include ESC, so ANSI escape sequences go with them
+    if name.chars().any(char::is_control) {
+        return Err(AuthError::InvalidName(
+            "Names can't contain line breaks, tabs, escape sequences or other control characters"
+                .to_string(),
+        ));
+    }
+    // Control characters are already out, so anything else unprintable is invisible
+    if name.chars().any(is_unprintable) {
+        return Err(AuthError::InvalidName(
+            "Names can't contain invisible or text-direction characters".to_string(),
+        ));
+    }
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.chars().count() > MAX_NAME {
+        return Err(AuthError::InvalidName(format!(
+            "Names can be at most {} characters long",
+            MAX_NAME
+        )));
+    }
+    Ok(name)
+}
+
+// Lowercase letters and digits only, so `B.a_D` still matches a blocklisted `bad`
+fn fold(text: &str) -> String {
+    text.nfkc()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(result: Result<String, AuthError>) -> String {
+        match result {
+            Err(AuthError::InvalidName(reason)) => reason,
+            other => panic!("expected an invalid name, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trims_normalises_and_collapses_spaces() {
+        // `e` and a combining acute accent become the single `é`
+        assert_eq!(
+            normalize("  Cafe\u{301}   Bob \t").unwrap(),
+            "Caf\u{e9} Bob"
+        );
+    }
+
+    #[test]
+    fn bounds_the_length_in_characters() {
+        assert_eq!(
+            normalize(&"é".repeat(MAX_NAME)).unwrap().chars().count(),
+            MAX_NAME
+        );
+        assert!(reason(normalize(&"x".repeat(MAX_NAME + 1))).contains("at most"));
+        assert_eq!(reason(normalize("   ")), "Please enter a name");
+    }
+
+    #[test]
+    fn turns_down_control_characters_and_escapes() {
+        for name in [
+            "Ev\u{1b}[31mil",
+            "two\nlines",
+            "tab\there",
+            "nul\0",
+            "\u{9b}2J",
+        ] {
+            assert!(
+                reason(normalize(name)).contains("control characters"),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn turns_down_invisible_characters() {
+        for name in [
+            "zero\u{200b}width",
+            "\u{202e}desrever",
+            "line\u{2028}break",
+            "\u{feff}bom",
+        ] {
+            assert!(
+                reason(normalize(name)).contains("invisible"),
+                "{:?} was accepted",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn blocks_words_inside_names_whatever_the_spelling() {
+        let policy = NamePolicy {
+            blocklist: vec![fold("Bad")],
+        };
+        for name in ["bad", "My B.a.d-Word", "xBADx", "b a d"] {
+            assert!(policy.check(name).is_err(), "{:?} was accepted", name);
+        }
+        assert_eq!(policy.check(" Bob ").unwrap(), "Bob");
+        assert!(NamePolicy::default().check("bad").is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+// outbox.rs
+
+use crate::protocol::GameMessage;
+use clap::ValueEnum;
+use log::warn;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::mem::{self, Discriminant};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::sync::{watch, Notify};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
+
+/// What to do with a connection whose outbound queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlowClientPolicy {
+    /// Drop queued board and lobby updates that a newer one replaces, keeping the latest;
+    /// disconnect only if that doesn't free any room
+    #[default]
+    DropStale,
+    /// Disconnect the client straight away; a player can still resume with their token
+    Disconnect,
+}
+
+// A queued frame, with the kind of snapshot it carries if a newer one can replace it
+#[derive(Debug)]
+struct Queued {
+    frame: Message,
+    snapshot: Option<Discriminant<GameMessage>>,
+    // When it was queued, for broadcasts, whose delivery time is measured
+    broadcast_at: Option<Instant>,
+}
+
+/// A frame for the writer to send, and when it was broadcast if it was.
+#[derive(Debug)]
+pub struct Outgoing {
+    pub frame: Message,
+    pub broadcast_at: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    frames: VecDeque<Queued>,
+    // A close frame is queued; nothing more is accepted
+    closing: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    // Only ever locked briefly, never across an `.await`
+    queue: Mutex<Queue>,
+    ready: Notify,
+    // Set once the connection is finished with, whether it closed, failed or fell behind
+    gone: watch::Sender<bool>,
+    capacity: usize,
+    policy: SlowClientPolicy,
+}
+
+/// Bounded queue of frames for one connection. Anything may queue a message without
+/// waiting; the connection's writer task sends them to the socket in order.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    shared: Arc<Shared>,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: SlowClientPolicy) -> Self {
+        Outbox {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(Queue::default()),
+                ready: Notify::new(),
+                gone: watch::Sender::new(false),
+                capacity,
+                policy,
+            }),
+        }
+    }
+
+    /// Queues `frame`, the encoding of `message`. Returns `false` if the connection is
+    /// gone, including when this message was one too many for a client that fell behind.
+    pub fn send(&self, frame: Message, message: &GameMessage) -> bool {
+        let snapshot = message.is_snapshot().then(|| mem::discriminant(message));
+        self.push(Queued {
+            frame,
+            snapshot,
+            broadcast_at: None,
+        })
+    }
+
+    /// Like `send`, for a message going out to everyone in a game or the lobby.
+    pub fn broadcast(&self, frame: Message, message: &GameMessage) -> bool {
+        let snapshot = message.is_snapshot().then(|| mem::discriminant(message));
+        self.push(Queued {
+            frame,
+            snapshot,
+            broadcast_at: Some(Instant::now()),
+        })
+    }
+
+    /// Sends whatever is still queued, then a close frame, and stops accepting messages.
+    pub fn close(&self) {
+        self.queue_close(None);
+    }
+
+    /// Like `close`, with a close code and a reason for the client.
+    pub fn close_with(&self, code: CloseCode, reason: &str) {
+        self.queue_close(Some(CloseFrame {
+            code,
+            reason: reason.to_string().into(),
+        }));
+    }
+
+    fn queue_close(&self, frame: Option<CloseFrame>) {
+        let mut queue = self.lock();
+        if queue.closing || self.is_gone() {
+            return;
+        }
+        queue.closing = true;
+        queue.frames.push_back(Queued {
+            frame: Message::Close(frame),
+            snapshot: None,
+            broadcast_at: None,
+        });
+        drop(queue);
+        self.shared.ready.notify_one();
+    }
+
+    pub fn is_gone(&self) -> bool {
+        *self.shared.gone.borrow()
+    }
+
+    /// Resolves once the connection is finished with.
+    pub async fn gone(&self) {
+        let mut gone = self.shared.gone.subscribe();
+        let _ = gone.wait_for(|gone| *gone).await;
+    }
+
+    /// Marks the connection as finished with and drops anything still queued.
+    pub fn finish(&self) {
+        self.lock().frames.clear();
+        self.shared.gone.send_replace(true);
+        self.shared.ready.notify_one();
+    }
+
+    /// Waits for the next frame to write, or `None` once there is nothing more to send.
+    pub async fn next(&self) -> Option<Outgoing> {
+        loop {
+            {
+                let mut queue = self.lock();
+                if self.is_gone() {
+                    return None;
+                }
+                if let Some(queued) = queue.frames.pop_front() {
+                    return Some(Outgoing {
+                        frame: queued.frame,
+                        broadcast_at: queued.broadcast_at,
+                    });
+                }
+                if queue.closing {
+                    return None;
+                }
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+
+    fn push(&self, queued: Queued) -> bool {
+        let mut queue = self.lock();
+        if queue.closing || self.is_gone() {
+            return false;
+        }
+        if queue.frames.len() >= self.shared.capacity && !self.make_room(&mut queue, &queued) {
+            drop(queue);
+            warn!(
+                "Disconnecting a client that fell {} messages behind",
+                self.shared.capacity
+            );
+            self.finish();
+            return false;
+        }
+        queue.frames.push_back(queued);
+        drop(queue);
+        self.shared.ready.notify_one();
+        true
+    }
+
+    // Drops snapshots that newer ones replace: every queued one of the incoming kind,
+    // and all but the latest of any other kind
+    fn make_room(&self, queue: &mut Queue, incoming: &Queued) -> bool {
+        if self.shared.policy == SlowClientPolicy::Disconnect {
+            return false;
+        }
+        let mut newer = Vec::new();
+        newer.extend(incoming.snapshot);
+        let mut kept = VecDeque::with_capacity(queue.frames.len());
+        while let Some(queued) = queue.frames.pop_back() {
+            match queued.snapshot {
+                Some(kind) if newer.contains(&kind) => continue,
+                Some(kind) => newer.push(kind),
+                None => {}
+            }
+            kept.push_front(queued);
+        }
+        queue.frames = kept;
+        queue.frames.len() < self.shared.capacity
+    }
+
+    // A panic elsewhere can't leave the queue half-updated, so a poisoned lock is still usable
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.shared
+            .queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::LobbyEntry;
+
+    fn announce(outbox: &Outbox, text: &str) -> bool {
+        let message = GameMessage::Announcement(text.to_string());
+        outbox.send(Message::text(text), &message)
+    }
+
+    fn lobby(outbox: &Outbox, text: &str) -> bool {
+        let message = GameMessage::Lobby(Vec::<LobbyEntry>::new());
+        outbox.broadcast(Message::text(text), &message)
+    }
+
+    // Every frame queued so far, as text
+    async fn drain(outbox: &Outbox) -> Vec<String> {
+        let mut frames = Vec::new();
+        while let Ok(Some(outgoing)) =
+            tokio::time::timeout(std::time::Duration::ZERO, outbox.next()).await
+        {
+            if let Message::Text(text) = outgoing.frame {
+                frames.push(text.to_string());
+            }
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn drop_stale_keeps_only_the_latest_snapshot() {
+        let outbox = Outbox::new(3, SlowClientPolicy::DropStale);
+        assert!(lobby(&outbox, "lobby 1"));
+        assert!(announce(&outbox, "hello"));
+        assert!(lobby(&outbox, "lobby 2"));
+        assert!(lobby(&outbox, "lobby 3"));
+        assert!(!outbox.is_gone());
+        assert_eq!(drain(&outbox).await, vec!["hello", "lobby 3"]);
+    }
+
+    #[tokio::test]
+    async fn drop_stale_disconnects_when_nothing_can_go() {
+        let outbox = Outbox::new(2, SlowClientPolicy::DropStale);
+        assert!(announce(&outbox, "one"));
+        assert!(announce(&outbox, "two"));
+        assert!(!announce(&outbox, "three"));
+        assert!(outbox.is_gone());
+        assert!(outbox.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn disconnect_policy_drops_the_client_once_full() {
+        let outbox = Outbox::new(2, SlowClientPolicy::Disconnect);
+        assert!(lobby(&outbox, "lobby 1"));
+        assert!(lobby(&outbox, "lobby 2"));
+        assert!(!lobby(&outbox, "lobby 3"));
+        assert!(outbox.is_gone());
+    }
+
+    #[tokio::test]
+    async fn close_sends_what_is_queued_first() {
+        let outbox = Outbox::new(4, SlowClientPolicy::DropStale);
+        assert!(announce(&outbox, "bye"));
+        outbox.close();
+        assert!(!announce(&outbox, "too late"));
+        let first = outbox.next().await.unwrap();
+        assert!(matches!(first.frame, Message::Text(text) if text.as_str() == "bye"));
+        let last = outbox.next().await.unwrap();
+        assert!(matches!(last.frame, Message::Close(None)));
+        assert!(outbox.next().await.is_none());
+    }
+}
@@ -1,39 +1,127 @@
 // player.rs
 
-use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio_tungstenite::tungstenite::protocol::Message;
+use crate::bot::Difficulty;
+use crate::outbox::Outbox;
+use crate::protocol::{GameMessage, Protocol};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{Duration, Instant};
 use tokio_tungstenite::WebSocketStream;
 
-#[derive(Debug, Clone, PartialEq)]
+/// Byte stream a WebSocket runs over, either plain TCP or TLS.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug> Transport for T {}
+
+pub type WsStream = WebSocketStream<Box<dyn Transport>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PlayerSymbol {
     X,
     O,
 }
 
+impl fmt::Display for PlayerSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerSymbol::X => write!(f, "X"),
+            PlayerSymbol::O => write!(f, "O"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Player {
     id: String,
     name: String,
     symbol: PlayerSymbol,
-    ws_sink: Arc<Mutex<futures::stream::SplitSink<WebSocketStream<TcpStream>, Message>>>,
+    protocol: Protocol,
+    // `None` for server-side bots, which have no connection
+    outbox: Option<Outbox>,
+    bot: Option<Difficulty>,
+    // Signed in to an account rather than playing as a guest
+    registered: bool,
+    resume_token: String,
+    // Identifies the connection currently attached to this seat
+    connection_id: String,
+    connected: bool,
+    // When this player's recent chat messages were sent, oldest first
+    recent_chats: VecDeque<Instant>,
+    // Rounds won in the current series
+    wins: usize,
 }
 
 impl Player {
-    pub fn new(
-        name: String,
-        symbol: PlayerSymbol,
-        ws_sink: Arc<Mutex<futures::stream::SplitSink<WebSocketStream<TcpStream>, Message>>>,
-    ) -> Self {
+    pub fn new(name: String, symbol: PlayerSymbol, protocol: Protocol, outbox: Outbox) -> Self {
+        Self {
+            name,
+            symbol,
+            protocol,
+            id: crate::utils::generate_id(),
+            outbox: Some(outbox),
+            bot: None,
+            registered: false,
+            resume_token: crate::utils::generate_token(),
+            connection_id: crate::utils::generate_id(),
+            connected: true,
+            recent_chats: VecDeque::new(),
+            wins: 0,
+        }
+    }
+
+    pub fn bot(difficulty: Difficulty, symbol: PlayerSymbol) -> Self {
+        Self {
+            name: format!("🤖 Computer ({})", difficulty.label()),
+            symbol,
+            protocol: Protocol::Text,
+            id: crate::utils::generate_id(),
+            outbox: None,
+            bot: Some(difficulty),
+            registered: false,
+            resume_token: crate::utils::generate_token(),
+            connection_id: crate::utils::generate_id(),
+            connected: true,
+            recent_chats: VecDeque::new(),
+            wins: 0,
+        }
+    }
+
+    /// A seat kept for a player who hasn't reconnected since the server restarted.
+    pub fn reserved(name: String, symbol: PlayerSymbol, resume_token: String) -> Self {
         Self {
             name,
             symbol,
+            protocol: Protocol::Text,
             id: crate::utils::generate_id(),
-            ws_sink,
+            outbox: None,
+            bot: None,
+            registered: false,
+            resume_token,
+            connection_id: crate::utils::generate_id(),
+            connected: false,
+            recent_chats: VecDeque::new(),
+            wins: 0,
         }
     }
 
+    pub fn is_bot(&self) -> bool {
+        self.bot.is_some()
+    }
+
+    pub fn get_bot_difficulty(&self) -> Option<Difficulty> {
+        self.bot
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.registered
+    }
+
+    pub fn set_registered(&mut self, registered: bool) {
+        self.registered = registered;
+    }
+
     pub fn set_symbol(&mut self, symbol: PlayerSymbol) {
         self.symbol = symbol;
     }
@@ -50,9 +138,79 @@ impl Player {
         self.name.clone()
     }
 
-    pub fn get_ws_sink(
-        &self,
-    ) -> Arc<Mutex<futures::stream::SplitSink<WebSocketStream<TcpStream>, Message>>> {
-        self.ws_sink.clone()
+    pub fn get_resume_token(&self) -> String {
+        self.resume_token.clone()
+    }
+
+    pub fn get_connection_id(&self) -> String {
+        self.connection_id.clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Marks the seat as waiting for a reconnect, unless another connection has already taken it.
+    pub fn mark_disconnected(&mut self, connection_id: &str) -> bool {
+        if self.connection_id != connection_id || !self.connected {
+            return false;
+        }
+        self.connected = false;
+        true
+    }
+
+    pub fn get_wins(&self) -> usize {
+        self.wins
+    }
+
+    pub fn add_win(&mut self) {
+        self.wins += 1;
+    }
+
+    pub fn clear_wins(&mut self) {
+        self.wins = 0;
+    }
+
+    /// Records a chat message unless `limit` have already been sent within `window`.
+    pub fn allow_chat(&mut self, limit: usize, window: Duration) -> bool {
+        let now = Instant::now();
+        while let Some(&sent) = self.recent_chats.front() {
+            if now.duration_since(sent) < window {
+                break;
+            }
+            self.recent_chats.pop_front();
+        }
+        if self.recent_chats.len() >= limit {
+            return false;
+        }
+        self.recent_chats.push_back(now);
+        true
+    }
+
+    /// Attaches a new connection to the seat and returns the outbox of the previous one.
+    pub fn reconnect(&mut self, outbox: Outbox, protocol: Protocol) -> Option<Outbox> {
+        self.protocol = protocol;
+        self.connection_id = crate::utils::generate_id();
+        self.connected = true;
+        self.outbox.replace(outbox)
+    }
+
+    /// Queues a broadcast for this player's connection. A connection that has gone away
+    /// or fallen too far behind is skipped; its session reports the loss on its own.
+    pub fn notify(&self, message: &GameMessage) {
+        if let Some(outbox) = self.live_outbox() {
+            outbox.broadcast(self.protocol.encode_broadcast(message), message);
+        }
+    }
+
+    /// Queues a direct answer to something this player asked for.
+    pub fn reply(&self, message: &GameMessage) {
+        if let Some(outbox) = self.live_outbox() {
+            outbox.send(self.protocol.encode(message), message);
+        }
+    }
+
+    fn live_outbox(&self) -> Option<&Outbox> {
+        self.outbox.as_ref().filter(|_| self.connected)
     }
 }
@@ -0,0 +1,953 @@
+// protocol.rs
+
+use crate::board::BoardConfig;
+use crate::bot::Difficulty;
+use crate::clock::TimeControl;
+use crate::game::{GameStatus, MoveRecord};
+use crate::player::PlayerSymbol;
+use crate::rating::{PlayerStats, RatingChange};
+use crate::replay::{Replay, ReplayStep};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Version of the structured JSON protocol spoken by this server.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// WebSocket subprotocol a client can request during the handshake to speak JSON.
+pub const JSON_SUBPROTOCOL: &str = "tictactoe.v1.json";
+
+/// Wire format used by a connection. Text is the default, human-readable protocol.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Text,
+    Json,
+}
+
+impl Protocol {
+    pub fn encode(&self, message: &GameMessage) -> Message {
+        match self {
+            Protocol::Text => Message::Text(message.to_string().into()),
+            Protocol::Json => Message::Text(encode_event(&message.to_event()).into()),
+        }
+    }
+
+    pub fn encode_broadcast(&self, message: &GameMessage) -> Message {
+        match self {
+            Protocol::Text => Message::Text(format!("📢 {}\n", message).into()),
+            Protocol::Json => self.encode(message),
+        }
+    }
+}
+
+fn encode_event(event: &ServerEvent) -> String {
+    serde_json::to_string(event).unwrap_or_else(|e| {
+        format!(
+            r#"{{"type":"error","code":"internal","message":"failed to encode event: {}"}}"#,
+            e
+        )
+    })
+}
+
+// Commands sent by JSON clients
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientCommand {
+    Hello {
+        version: u32,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    SetName {
+        name: String,
+    },
+    // Signs in, creates an account or asks for a key challenge in place of `set_name`
+    Auth {
+        method: AuthMethod,
+        name: String,
+        #[serde(default)]
+        password: Option<String>,
+        // Base64 ed25519 public key, when registering
+        #[serde(default)]
+        public_key: Option<String>,
+        // Base64 signature of the challenge, when signing in with a key
+        #[serde(default)]
+        signature: Option<String>,
+    },
+    Create {
+        #[serde(default)]
+        board: Option<BoardConfig>,
+        // Play a best-of-N match instead of an open-ended series
+        #[serde(default)]
+        best_of: Option<usize>,
+        // Without one the server's per-move limit applies
+        #[serde(default)]
+        time_control: Option<TimeControl>,
+    },
+    Join {
+        game_id: String,
+    },
+    QuickMatch,
+    PlayComputer {
+        difficulty: Difficulty,
+    },
+    Watch {
+        game_id: String,
+    },
+    Resume {
+        token: String,
+    },
+    Replay {
+        game_id: String,
+    },
+    // The highest-rated players, ten unless `limit` says otherwise
+    Leaderboard {
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    Stats {
+        name: String,
+    },
+    // Lists games; `subscribe` turns live updates on or off while at the menu
+    Lobby {
+        #[serde(default)]
+        open: bool,
+        #[serde(default)]
+        board: Option<BoardConfig>,
+        #[serde(default)]
+        subscribe: Option<bool>,
+    },
+    Say {
+        text: String,
+    },
+    Move {
+        #[serde(default)]
+        cell: Option<usize>,
+        #[serde(default)]
+        row: Option<usize>,
+        #[serde(default)]
+        col: Option<usize>,
+    },
+    Restart,
+    // Asks the opponent to let you take back your last move
+    Takeback,
+    TakebackReply {
+        accept: bool,
+    },
+    Exit,
+    Status,
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Guest,
+    Password,
+    Register,
+    Key,
+}
+
+impl ClientCommand {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+}
+
+// Events sent to JSON clients
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Hello {
+        version: u32,
+    },
+    Prompt {
+        prompt: PromptKind,
+    },
+    // Sign the challenge with the account's key and send it back in an `auth` command
+    AuthChallenge {
+        challenge: String,
+    },
+    AccountCreated {
+        name: String,
+    },
+    SignedIn {
+        name: String,
+        guest: bool,
+    },
+    GameCreated {
+        game_id: String,
+        board: BoardConfig,
+    },
+    GameJoined {
+        game_id: String,
+    },
+    PlayerJoined {
+        name: String,
+    },
+    PlayerLeft {
+        name: String,
+    },
+    Watching {
+        game_id: String,
+    },
+    SpectatorJoined {
+        name: String,
+        spectators: usize,
+    },
+    SpectatorLeft {
+        name: String,
+        spectators: usize,
+    },
+    // `reason` is set when the server's administrators closed the game
+    GameClosed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    ResumeToken {
+        token: String,
+    },
+    Resumed {
+        game_id: String,
+    },
+    PlayerAwaitingReconnect {
+        name: String,
+        seconds: u64,
+    },
+    PlayerReconnected {
+        name: String,
+    },
+    Lobby {
+        games: Vec<LobbyEntry>,
+    },
+    LobbySubscription {
+        subscribed: bool,
+    },
+    Chat {
+        #[serde(flatten)]
+        message: ChatMessage,
+    },
+    ChatHistory {
+        messages: Vec<ChatMessage>,
+    },
+    State {
+        game: GameSnapshot,
+    },
+    GameOver,
+    GameRestarted,
+    RematchOffered {
+        name: String,
+    },
+    // `winner` is `None` when the match ends level
+    MatchOver {
+        winner: Option<String>,
+    },
+    TakebackRequested {
+        name: String,
+        seconds: u64,
+    },
+    TakebackAccepted {
+        name: String,
+        moves: usize,
+    },
+    TakebackDeclined {
+        name: String,
+    },
+    TakebackExpired {
+        name: String,
+    },
+    TakebackCancelled,
+    LostOnTime {
+        name: String,
+    },
+    RatingsUpdated {
+        changes: Vec<RatingChange>,
+    },
+    Leaderboard {
+        players: Vec<PlayerStats>,
+    },
+    Stats {
+        stats: PlayerStats,
+    },
+    Replay {
+        replay: Replay,
+    },
+    ReplayMove {
+        number: usize,
+        total: usize,
+        #[serde(rename = "move")]
+        record: MoveRecord,
+    },
+    Help {
+        commands: Vec<&'static str>,
+    },
+    Announcement {
+        message: String,
+    },
+    ShuttingDown {
+        seconds: u64,
+    },
+    // The server stopped mid-round; a `resumable` game can be resumed once it is back
+    GameAborted {
+        resumable: bool,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptKind {
+    Name,
+    Menu,
+    GameId,
+    Difficulty,
+    Board,
+    ResumeToken,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidInput,
+    InvalidChoice,
+    InvalidCommand,
+    InvalidBoard,
+    UnsupportedVersion,
+    GameNotFound,
+    GameFull,
+    GameFinished,
+    NoOpenGames,
+    GameNotInProgress,
+    WaitingForPlayers,
+    NotYourTurn,
+    InvalidMove,
+    CantRestart,
+    Inactive,
+    SpectatorReadOnly,
+    InvalidResumeToken,
+    ChatTooLong,
+    ChatRateLimited,
+    ServerBusy,
+    NoReplay,
+    TakebackNotAllowed,
+    TakebackPending,
+    NoTakebackRequest,
+    RematchPending,
+    MatchOver,
+    PlayerNotFound,
+    InvalidName,
+    InvalidCredentials,
+    NameTaken,
+    NameInUse,
+    AuthFailed,
+    GuestsNotAllowed,
+    SignedInElsewhere,
+}
+
+impl ErrorCode {
+    /// The code as JSON clients see it, e.g. `game_full`.
+    pub fn name(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerInfo {
+    pub name: String,
+    pub symbol: PlayerSymbol,
+    pub bot: bool,
+    pub connected: bool,
+    // Rounds won in the current series
+    pub wins: usize,
+    // What's left of the player's bank, or of their move's limit while it's their turn
+    pub time_left_ms: Option<u64>,
+}
+
+/// Machine-readable view of a game, sent to JSON clients on every board change.
+#[derive(Debug, Clone, Serialize)]
+pub struct GameSnapshot {
+    pub game_id: String,
+    #[serde(flatten)]
+    pub config: BoardConfig,
+    pub board: Vec<Option<PlayerSymbol>>,
+    pub players: Vec<PlayerInfo>,
+    pub spectators: usize,
+    pub status: GameStatus,
+    pub turn: Option<PlayerInfo>,
+    pub winner: Option<PlayerInfo>,
+    pub winning_line: Option<Vec<usize>>,
+    pub draw: bool,
+    pub round: usize,
+    pub draws: usize,
+    pub best_of: Option<usize>,
+    pub match_over: bool,
+    pub time_control: TimeControl,
+    // The round was decided by a player running out of time
+    pub won_on_time: bool,
+}
+
+/// A chat line as broadcast to everyone in a game.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatMessage {
+    pub name: String,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+impl fmt::Display for ChatMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "💬 [{}] {}: {}",
+            self.sent_at.format("%H:%M:%S"),
+            self.name,
+            self.text
+        )
+    }
+}
+
+/// A game as listed in the lobby.
+#[derive(Debug, Clone, Serialize)]
+pub struct LobbyEntry {
+    pub game_id: String,
+    pub creator: String,
+    #[serde(flatten)]
+    pub config: BoardConfig,
+    pub status: GameStatus,
+    pub players: usize,
+    pub spectators: usize,
+    pub bot: bool,
+    pub best_of: Option<usize>,
+    pub time_control: TimeControl,
+    pub age_secs: u64,
+}
+
+impl LobbyEntry {
+    /// Whether a new player can take a seat. A restored game waits with every seat held
+    /// for a returning player, so it isn't open.
+    pub fn is_open(&self) -> bool {
+        self.status == GameStatus::WaitingForPlayers && self.players < 2
+    }
+}
+
+impl fmt::Display for LobbyEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            GameStatus::WaitingForPlayers => "⏳ waiting",
+            GameStatus::InProgress => "🎮 playing",
+            GameStatus::Finished => "🏁 finished",
+        };
+        let age = match self.age_secs {
+            secs if secs < 60 => format!("{}s", secs),
+            secs if secs < 3600 => format!("{}m", secs / 60),
+            secs => format!("{}h", secs / 3600),
+        };
+        let best_of = match self.best_of {
+            Some(best_of) => format!(" | best of {}", best_of),
+            None => String::new(),
+        };
+        write!(
+            f,
+            "{} | {}{} | {}{} | ⏱️ {} | {} | {}/2 players | 👀 {} | {} ago",
+            self.game_id,
+            self.creator,
+            if self.bot { " vs 🤖" } else { "" },
+            self.config,
+            best_of,
+            self.time_control,
+            status,
+            self.players,
+            self.spectators,
+            age
+        )
+    }
+}
+
+/// A rendered board for text clients together with its structured snapshot.
+#[derive(Debug, Clone)]
+pub struct GameView {
+    pub text: String,
+    pub snapshot: GameSnapshot,
+}
+
+// Enum to represent different game messages
+#[derive(Debug, Clone)]
+pub enum GameMessage {
+    // Whether guests may play
+    Welcome(bool),
+    // Asks again after a name was turned down
+    ChooseAnotherName,
+    ProtocolAccepted,
+    AuthChallenge(String),
+    AccountCreated(String),
+    // Name, and whether it belongs to an account
+    SignedIn(String, bool),
+    SignedInElsewhere,
+    ChooseOption,
+    ChooseBoard,
+    GameCreated(String, BoardConfig),
+    EnterGameId,
+    ChooseDifficulty,
+    GameJoined(String),
+    PlayerJoined(String),
+    InvalidChoice,
+    InvalidInput,
+    WaitingForPlayers,
+    GameOver,
+    CantRestart,
+    PlayerDisconnected(String),
+    Watching(String),
+    SpectatorJoined(String, usize),
+    SpectatorLeft(String, usize),
+    SpectatorHelp,
+    GameClosed,
+    // Closed by the server's administrators, with an optional reason
+    ClosedByAdmin(Option<String>),
+    ResumeToken(String),
+    EnterResumeToken,
+    Resumed(String),
+    PlayerAwaitingReconnect(String, u64),
+    PlayerReconnected(String),
+    Lobby(Vec<LobbyEntry>),
+    LobbySubscribed(bool),
+    Chat(ChatMessage),
+    ChatHistory(Vec<ChatMessage>),
+    GameRestarted,
+    RematchOffered(String),
+    MatchOver(Option<String>),
+    TakebackRequested(String, u64),
+    TakebackAccepted(String, usize),
+    TakebackDeclined(String),
+    TakebackExpired(String),
+    TakebackCancelled,
+    LostOnTime(String),
+    RatingsUpdated(Vec<RatingChange>),
+    Leaderboard(Vec<PlayerStats>),
+    Stats(PlayerStats),
+    EnterReplayId,
+    Replay(Replay),
+    ReplayMove(ReplayStep),
+    ReplayExport(Replay),
+    Error(ErrorCode, String),
+    InactiveDisconnect,
+    Help,
+    Announcement(String),
+    // Seconds until the server stops
+    ShuttingDown(u64),
+    // Whether the game was saved to resume after a restart
+    GameAborted(bool),
+    GameState(GameView),
+    GameStatus(GameView),
+    // Custom(String),
+}
+
+impl GameMessage {
+    /// Whether a newer message of the same kind makes this one obsolete, as with a
+    /// board or lobby that has since changed again.
+    pub fn is_snapshot(&self) -> bool {
+        matches!(self, GameMessage::GameState(_) | GameMessage::Lobby(_))
+    }
+
+    pub fn to_event(&self) -> ServerEvent {
+        match self {
+            GameMessage::Welcome(_) | GameMessage::ChooseAnotherName => ServerEvent::Prompt {
+                prompt: PromptKind::Name,
+            },
+            GameMessage::ProtocolAccepted => ServerEvent::Hello {
+                version: PROTOCOL_VERSION,
+            },
+            GameMessage::AuthChallenge(challenge) => ServerEvent::AuthChallenge {
+                challenge: challenge.clone(),
+            },
+            GameMessage::AccountCreated(name) => ServerEvent::AccountCreated { name: name.clone() },
+            GameMessage::SignedIn(name, registered) => ServerEvent::SignedIn {
+                name: name.clone(),
+                guest: !registered,
+            },
+            GameMessage::SignedInElsewhere => error_event(
+                ErrorCode::SignedInElsewhere,
+                "Signed in from another connection",
+            ),
+            GameMessage::ChooseOption => ServerEvent::Prompt {
+                prompt: PromptKind::Menu,
+            },
+            GameMessage::EnterGameId => ServerEvent::Prompt {
+                prompt: PromptKind::GameId,
+            },
+            GameMessage::ChooseDifficulty => ServerEvent::Prompt {
+                prompt: PromptKind::Difficulty,
+            },
+            GameMessage::ChooseBoard => ServerEvent::Prompt {
+                prompt: PromptKind::Board,
+            },
+            GameMessage::GameCreated(id, config) => ServerEvent::GameCreated {
+                game_id: id.clone(),
+                board: *config,
+            },
+            GameMessage::GameJoined(id) => ServerEvent::GameJoined {
+                game_id: id.clone(),
+            },
+            GameMessage::PlayerJoined(name) => ServerEvent::PlayerJoined { name: name.clone() },
+            GameMessage::PlayerDisconnected(name) => ServerEvent::PlayerLeft { name: name.clone() },
+            GameMessage::Watching(id) => ServerEvent::Watching {
+                game_id: id.clone(),
+            },
+            GameMessage::SpectatorJoined(name, count) => ServerEvent::SpectatorJoined {
+                name: name.clone(),
+                spectators: *count,
+            },
+            GameMessage::SpectatorLeft(name, count) => ServerEvent::SpectatorLeft {
+                name: name.clone(),
+                spectators: *count,
+            },
+            GameMessage::SpectatorHelp => ServerEvent::Help {
+                commands: vec!["status", "exit", "help"],
+            },
+            GameMessage::GameClosed => ServerEvent::GameClosed { reason: None },
+            GameMessage::ClosedByAdmin(reason) => ServerEvent::GameClosed {
+                reason: Some(
+                    reason
+                        .clone()
+                        .unwrap_or_else(|| "Closed by an administrator".to_string()),
+                ),
+            },
+            GameMessage::ResumeToken(token) => ServerEvent::ResumeToken {
+                token: token.clone(),
+            },
+            GameMessage::EnterResumeToken => ServerEvent::Prompt {
+                prompt: PromptKind::ResumeToken,
+            },
+            GameMessage::Resumed(id) => ServerEvent::Resumed {
+                game_id: id.clone(),
+            },
+            GameMessage::PlayerAwaitingReconnect(name, seconds) => {
+                ServerEvent::PlayerAwaitingReconnect {
+                    name: name.clone(),
+                    seconds: *seconds,
+                }
+            }
+            GameMessage::PlayerReconnected(name) => {
+                ServerEvent::PlayerReconnected { name: name.clone() }
+            }
+            GameMessage::Lobby(games) => ServerEvent::Lobby {
+                games: games.clone(),
+            },
+            GameMessage::LobbySubscribed(subscribed) => ServerEvent::LobbySubscription {
+                subscribed: *subscribed,
+            },
+            GameMessage::Chat(message) => ServerEvent::Chat {
+                message: message.clone(),
+            },
+            GameMessage::ChatHistory(messages) => ServerEvent::ChatHistory {
+                messages: messages.clone(),
+            },
+            GameMessage::RematchOffered(name) => ServerEvent::RematchOffered { name: name.clone() },
+            GameMessage::MatchOver(winner) => ServerEvent::MatchOver {
+                winner: winner.clone(),
+            },
+            GameMessage::TakebackRequested(name, seconds) => ServerEvent::TakebackRequested {
+                name: name.clone(),
+                seconds: *seconds,
+            },
+            GameMessage::TakebackAccepted(name, moves) => ServerEvent::TakebackAccepted {
+                name: name.clone(),
+                moves: *moves,
+            },
+            GameMessage::TakebackDeclined(name) => {
+                ServerEvent::TakebackDeclined { name: name.clone() }
+            }
+            GameMessage::TakebackExpired(name) => {
+                ServerEvent::TakebackExpired { name: name.clone() }
+            }
+            GameMessage::TakebackCancelled => ServerEvent::TakebackCancelled,
+            GameMessage::LostOnTime(name) => ServerEvent::LostOnTime { name: name.clone() },
+            GameMessage::RatingsUpdated(changes) => ServerEvent::RatingsUpdated {
+                changes: changes.clone(),
+            },
+            GameMessage::Leaderboard(players) => ServerEvent::Leaderboard {
+                players: players.clone(),
+            },
+            GameMessage::Stats(stats) => ServerEvent::Stats {
+                stats: stats.clone(),
+            },
+            GameMessage::EnterReplayId => ServerEvent::Prompt {
+                prompt: PromptKind::GameId,
+            },
+            GameMessage::Replay(replay) | GameMessage::ReplayExport(replay) => {
+                ServerEvent::Replay {
+                    replay: replay.clone(),
+                }
+            }
+            GameMessage::ReplayMove(step) => ServerEvent::ReplayMove {
+                number: step.number,
+                total: step.total,
+                record: step.record.clone(),
+            },
+            GameMessage::InvalidChoice => error_event(ErrorCode::InvalidChoice, "Invalid choice"),
+            GameMessage::InvalidInput => error_event(ErrorCode::InvalidInput, "Invalid input"),
+            GameMessage::WaitingForPlayers => error_event(
+                ErrorCode::WaitingForPlayers,
+                "Waiting for players to join the game",
+            ),
+            GameMessage::CantRestart => error_event(
+                ErrorCode::CantRestart,
+                "You can't restart game before finishing current game",
+            ),
+            GameMessage::InactiveDisconnect => {
+                error_event(ErrorCode::Inactive, "Disconnected due to inactivity")
+            }
+            GameMessage::Error(code, message) => error_event(*code, message),
+            GameMessage::GameOver => ServerEvent::GameOver,
+            GameMessage::GameRestarted => ServerEvent::GameRestarted,
+            GameMessage::Announcement(message) => ServerEvent::Announcement {
+                message: message.clone(),
+            },
+            GameMessage::ShuttingDown(seconds) => ServerEvent::ShuttingDown { seconds: *seconds },
+            GameMessage::GameAborted(resumable) => ServerEvent::GameAborted {
+                resumable: *resumable,
+            },
+            GameMessage::Help => ServerEvent::Help {
+                commands: vec![
+                    "move",
+                    "say",
+                    "takeback",
+                    "takeback_reply",
+                    "restart",
+                    "exit",
+                    "status",
+                    "help",
+                ],
+            },
+            GameMessage::GameState(view) | GameMessage::GameStatus(view) => ServerEvent::State {
+                game: view.snapshot.clone(),
+            },
+        }
+    }
+}
+
+fn error_event(code: ErrorCode, message: &str) -> ServerEvent {
+    ServerEvent::Error {
+        code,
+        message: message.to_string(),
+    }
+}
+
+// Implementation for GameMessage to convert to string
+impl fmt::Display for GameMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text: String = match self {
+            GameMessage::Welcome(true) => "🎉 Welcome to Tic-Tac-Toe! Please enter your name to play as a guest, or sign in with `login <name> <password>`, `register <name> <password>` or `key <name>`:".into(),
+            GameMessage::Welcome(false) => "🎉 Welcome to Tic-Tac-Toe! Please sign in with `login <name> <password>` or `key <name>`, or create an account with `register <name> <password>`:".into(),
+            GameMessage::ChooseAnotherName => "✏️ Please enter another name:".into(),
+            GameMessage::ProtocolAccepted => format!("✅ Using protocol version {}", PROTOCOL_VERSION),
+            GameMessage::AuthChallenge(challenge) => format!("🔏 Challenge: {}\nSign it with your key and send `signature <base64-signature>`.", challenge),
+            GameMessage::AccountCreated(name) => format!("✅ Account {} created!", name),
+            GameMessage::SignedIn(name, true) => format!("🔓 Signed in as {}", name),
+            GameMessage::SignedIn(name, false) => format!("👤 Playing as guest {}", name),
+            GameMessage::SignedInElsewhere => "🔒 You signed in from another connection, closing this one.".into(),
+            GameMessage::ChooseOption => "🕹️ Choose an option:\n1️⃣ Create a new game\n2️⃣ Join a game by ID\n3️⃣ Quick match (join any open game)\n4️⃣ Play vs computer\n5️⃣ Watch a game by ID\n6️⃣ Resume a game after a dropped connection\n7️⃣ Browse the lobby (or type `lobby open`, `lobby subscribe`)\n8️⃣ Replay a finished game (or type `replay <game-id>`, `replay <game-id> json` to export it)\n9️⃣ Leaderboard (or type `leaderboard 25`, `stats` for your own rating, `stats <name>`)".into(),
+            GameMessage::ChooseBoard => "📐 Choose the board: press Enter for classic 3x3, or enter `N`, `N K`, `ROWS COLS K` or `RxC K` (e.g. `15 5` for Gomoku). Add `bo3`, or any `boN`, to play a best-of-N match, and a time control: `30s` per move, `5m` for a 5-minute bank each, or `5+3` to add 3 seconds to the bank after every move (e.g. `15 5 bo3 5+3`). Without one, the server's per-move limit applies".into(),
+            GameMessage::GameCreated(id, config) => format!("✅ Game created! Your game ID is: {}\n📐 Board: {}\nWaiting for another player to join...", id, config),
+            GameMessage::EnterGameId => "🔍 Enter the game ID to join:".into(),
+            GameMessage::ChooseDifficulty => "🤖 Choose difficulty:\n1️⃣ Easy (random moves)\n2️⃣ Medium (win, block, centre, corners)\n3️⃣ Hard (perfect play)".into(),
+            GameMessage::GameJoined(id) => format!("🎮 Joined game: {}", id),
+            GameMessage::PlayerJoined(name) => format!("Player {} has joined the game!\n", name),
+            GameMessage::InvalidChoice => "❌ Invalid choice, please restart.".into(),
+            GameMessage::InvalidInput => "❌ Invalid Input: Enter a number from 1 to 9, or a coordinate like `c3` on larger boards".into(),
+            GameMessage::WaitingForPlayers => "⏳ Waiting for players to join the game...".into(),
+            GameMessage::GameOver => "🎉 Game over! Type `RESTART` to offer a rematch or `EXIT` to leave.".into(),
+            GameMessage::CantRestart => "❌ Error: You can't restart game before finishing current game ❗".into(),
+            GameMessage::PlayerDisconnected(name) => format!("❗ Player {} has left the game. ⏳ Waiting for a new player...", name),
+            GameMessage::Watching(id) => format!("👀 Watching game {} (read-only). Type 'status' to see the board or 'exit' to stop watching.", id),
+            GameMessage::SpectatorJoined(name, count) => format!("👀 {} is now watching ({} watching)", name, count),
+            GameMessage::SpectatorLeft(name, count) => format!("👀 {} stopped watching ({} watching)", name, count),
+            GameMessage::SpectatorHelp => "🆘 Spectator Commands:\n- Type 'status' to see the board\n- Type 'exit' to stop watching\nSpectators can't make moves, chat or restart the game.".into(),
+            GameMessage::GameClosed => "🚪 All players have left, the game is closed.".into(),
+            GameMessage::ClosedByAdmin(None) => "🛑 The game was closed by an administrator.".into(),
+            GameMessage::ClosedByAdmin(Some(reason)) => format!("🛑 The game was closed by an administrator: {}", reason),
+            GameMessage::ResumeToken(token) => format!("🔑 Your resume token is: {}\nIf your connection drops, reconnect and choose option 6 with this token to get your seat back.", token),
+            GameMessage::EnterResumeToken => "🔑 Enter your resume token:".into(),
+            GameMessage::Resumed(id) => format!("🔌 Reconnected to game {}", id),
+            GameMessage::PlayerAwaitingReconnect(name, seconds) => format!("⚠️ {} disconnected, waiting {} seconds for them to reconnect...", name, seconds),
+            GameMessage::PlayerReconnected(name) => format!("🔌 {} reconnected!", name),
+            GameMessage::Lobby(games) if games.is_empty() => "🏟️ No games in the lobby.".into(),
+            GameMessage::Lobby(games) => {
+                let lines: Vec<String> = games.iter().map(|game| format!("- {}", game)).collect();
+                format!("🏟️ Lobby ({} listed):\n{}", games.len(), lines.join("\n"))
+            }
+            GameMessage::LobbySubscribed(true) => "🔔 Subscribed to lobby updates. Type `lobby unsubscribe` to stop.".into(),
+            GameMessage::LobbySubscribed(false) => "🔕 Unsubscribed from lobby updates.".into(),
+            GameMessage::Chat(message) => message.to_string(),
+            GameMessage::ChatHistory(messages) => {
+                let lines: Vec<String> = messages.iter().map(|m| m.to_string()).collect();
+                format!("🗨️ Recent chat:\n{}", lines.join("\n"))
+            }
+            GameMessage::GameRestarted => "🔄 Rematch! Symbols are swapped and the other player moves first.".into(),
+            GameMessage::RematchOffered(name) => format!("🔁 {} wants a rematch. Type `restart` to accept.", name),
+            GameMessage::MatchOver(Some(winner)) => format!("🏆 {} wins the match! Type `EXIT` to leave.", winner),
+            GameMessage::MatchOver(None) => "🤝 The match ends level! Type `EXIT` to leave.".into(),
+            GameMessage::TakebackRequested(name, seconds) => format!("↩️ {} asks to take back their last move. Their opponent has {} seconds to type `accept` or `decline`.", name, seconds),
+            GameMessage::TakebackAccepted(name, 1) => format!("↩️ {} accepted the takeback, 1 move undone.", name),
+            GameMessage::TakebackAccepted(name, moves) => format!("↩️ {} accepted the takeback, {} moves undone.", name, moves),
+            GameMessage::TakebackDeclined(name) => format!("🚫 {} declined the takeback.", name),
+            GameMessage::TakebackExpired(name) => format!("⌛ {}'s takeback request expired without an answer.", name),
+            GameMessage::TakebackCancelled => "🚫 The takeback request lapsed because a move was made.".into(),
+            GameMessage::LostOnTime(name) => format!("⌛ {} ran out of time and loses the round!", name),
+            GameMessage::RatingsUpdated(changes) => {
+                let changes: Vec<String> = changes.iter().map(|c| format!("{} {} ({:+})", c.name, c.rating, c.change)).collect();
+                format!("📈 Ratings: {}", changes.join(", "))
+            }
+            GameMessage::Leaderboard(players) if players.is_empty() => "🏆 No rated games yet.".into(),
+            GameMessage::Leaderboard(players) => {
+                let lines: Vec<String> = players.iter().map(|p| format!("{}", p)).collect();
+                format!("🏆 Leaderboard:\n{}", lines.join("\n"))
+            }
+            GameMessage::Stats(stats) => {
+                let games: Vec<String> = stats.recent.iter().map(|game| format!("- {}", game)).collect();
+                format!(
+                    "📊 {}: rating {} (#{}) | {} wins, {} losses, {} draws\nRecent games:\n{}",
+                    stats.name, stats.rating, stats.rank, stats.wins, stats.losses, stats.draws, games.join("\n")
+                )
+            }
+            GameMessage::EnterReplayId => "🎬 Enter the ID of the game to replay:".into(),
+            GameMessage::Replay(replay) => format!("🎬 Replay of {}\n📝 {}\nPress Enter for the next move, or type `stop` to go back to the menu.", replay, replay.notation),
+            GameMessage::ReplayMove(step) => {
+                let result = match &step.result {
+                    Some(result) => format!("\n🏁 {}", result),
+                    None => String::new(),
+                };
+                format!(
+                    "🎬 Move {}/{}: {} ({}) played {} at {}\n\n{}{}",
+                    step.number,
+                    step.total,
+                    step.record.player,
+                    step.record.symbol,
+                    step.cell,
+                    step.record.at.format("%H:%M:%S"),
+                    step.board,
+                    result
+                )
+            }
+            GameMessage::ReplayExport(replay) => serde_json::to_string_pretty(replay)
+                .unwrap_or_else(|e| format!("❌ Error: failed to export the replay: {}", e)),
+            GameMessage::Error(_, e) => format!("❌ Error: {}", e),
+            GameMessage::InactiveDisconnect => "❗ Disconnected due to inactivity ⏰".into(),
+            GameMessage::Help => "🆘 Available Commands:\n- Enter 1-9 to make a move (on larger boards use a coordinate like `c3` or `row col`)\n- Type 'takeback' to ask your opponent to undo your last move, and 'accept' or 'decline' to answer theirs\n- Type 'restart' after a round to offer or accept a rematch\n- Type 'exit' to leave\n- Type 'status' to check the game status\n- Type 'say <message>' to chat".into(),
+            GameMessage::Announcement(message) => format!("📣 Announcement: {}", message),
+            GameMessage::ShuttingDown(seconds) => format!("⚠️ The server is shutting down in {} seconds. Games still being played then will be stopped.", seconds),
+            GameMessage::GameAborted(true) => "🛑 The server is shutting down before the game finished. It is saved: once the server is back, reconnect and resume it with your token.".into(),
+            GameMessage::GameAborted(false) => "🛑 The server is shutting down before the game finished, so it was aborted.".into(),
+            GameMessage::GameState(view) => view.text.clone(),
+            GameMessage::GameStatus(view) => format!("📊 Game Status: {}", view.text),
+
+            // GameMessage::Custom(msg) => msg.clone(),
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn encoded(protocol: Protocol, message: &GameMessage) -> String {
+        match protocol.encode(message) {
+            Message::Text(text) => text.to_string(),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    fn event(message: &GameMessage) -> Value {
+        serde_json::from_str(&encoded(Protocol::Json, message)).unwrap()
+    }
+
+    #[test]
+    fn parses_commands_by_their_type() {
+        assert!(matches!(
+            ClientCommand::parse(r#"{"type":"hello","version":1,"name":"alice"}"#),
+            Ok(ClientCommand::Hello { version: 1, name: Some(name) }) if name == "alice"
+        ));
+        assert!(matches!(
+            ClientCommand::parse(r#"{"type":"move","row":1,"col":2}"#),
+            Ok(ClientCommand::Move {
+                cell: None,
+                row: Some(1),
+                col: Some(2)
+            })
+        ));
+        assert!(matches!(
+            ClientCommand::parse(r#"{"type":"takeback_reply","accept":false}"#),
+            Ok(ClientCommand::TakebackReply { accept: false })
+        ));
+        assert!(matches!(
+            ClientCommand::parse(r#"{"type":"lobby"}"#),
+            Ok(ClientCommand::Lobby {
+                open: false,
+                board: None,
+                subscribe: None
+            })
+        ));
+    }
+
+    #[test]
+    fn turns_down_malformed_commands() {
+        for text in [
+            "",
+            "1",
+            "not json",
+            r#"{"type":"dance"}"#,
+            r#"{"type":"join"}"#,
+            r#"{"type":"hello","version":"one"}"#,
+            r#"{"version":1}"#,
+        ] {
+            assert!(
+                ClientCommand::parse(text).is_err(),
+                "{:?} was accepted",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn json_clients_get_tagged_events() {
+        assert_eq!(
+            event(&GameMessage::Welcome(true)),
+            json!({"type": "prompt", "prompt": "name"})
+        );
+        assert_eq!(
+            event(&GameMessage::SignedIn("alice".to_string(), false)),
+            json!({"type": "signed_in", "name": "alice", "guest": true})
+        );
+        assert_eq!(
+            event(&GameMessage::Error(
+                ErrorCode::NotYourTurn,
+                "Wait for your turn".to_string()
+            )),
+            json!({"type": "error", "code": "not_your_turn", "message": "Wait for your turn"})
+        );
+    }
+
+    #[test]
+    fn text_clients_get_the_message_text() {
+        let message = GameMessage::Error(ErrorCode::InvalidInput, "Nope".to_string());
+        assert_eq!(encoded(Protocol::Text, &message), "❌ Error: Nope");
+        match Protocol::Text.encode_broadcast(&message) {
+            Message::Text(text) => assert_eq!(text.as_str(), "📢 ❌ Error: Nope\n"),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_codes_are_named_as_on_the_wire() {
+        assert_eq!(ErrorCode::GameNotFound.name(), "game_not_found");
+        assert_eq!(ErrorCode::InvalidInput.name(), "invalid_input");
+    }
+}
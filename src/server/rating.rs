@@ -0,0 +1,306 @@
+// rating.rs
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+// Most a single game can move a rating
+const K_FACTOR: f64 = 32.0;
+// Games kept for `stats`, newest first
+const RECENT_GAMES: usize = 10;
+// Most players `leaderboard` lists at once
+pub const MAX_LEADERBOARD: usize = 100;
+pub const DEFAULT_LEADERBOARD: usize = 10;
+
+/// How a rated game was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    Board,
+    // The loser ran out of time
+    Time,
+    // The loser left, or never came back, in the middle of a round
+    Abandoned,
+}
+
+/// A game's result from one player's side.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// One rated game as listed by `stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RatedGame {
+    pub game_id: String,
+    pub opponent: String,
+    pub result: GameResult,
+    pub ending: Ending,
+    // Rating points won or lost
+    pub change: i64,
+    pub at: DateTime<Utc>,
+}
+
+/// A player's new rating after a game.
+#[derive(Debug, Clone, Serialize)]
+pub struct RatingChange {
+    pub name: String,
+    pub rating: i64,
+    pub change: i64,
+}
+
+/// A player's standing, as shown by `leaderboard` and `stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerStats {
+    pub name: String,
+    pub rank: usize,
+    pub rating: i64,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    // Empty in leaderboard rows
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recent: Vec<RatedGame>,
+}
+
+#[derive(Debug, Clone)]
+struct Rating {
+    // As last seen; players are found by name regardless of case
+    name: String,
+    rating: f64,
+    wins: usize,
+    losses: usize,
+    draws: usize,
+    recent: VecDeque<RatedGame>,
+}
+
+impl Rating {
+    fn new(name: &str) -> Self {
+        Rating {
+            name: name.to_string(),
+            rating: INITIAL_RATING,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            recent: VecDeque::new(),
+        }
+    }
+}
+
+/// Elo ratings of everyone who has finished a game against another account. Games
+/// against bots or guests aren't rated.
+#[derive(Debug, Default)]
+pub struct Ratings {
+    players: HashMap<String, Rating>,
+}
+
+fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+impl Ratings {
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    /// Rates a game between `players`. `winner` is `None` for a draw.
+    pub fn record(
+        &mut self,
+        game_id: &str,
+        players: [&str; 2],
+        winner: Option<&str>,
+        ending: Ending,
+        at: DateTime<Utc>,
+    ) -> Vec<RatingChange> {
+        let [first, second] = players;
+        let current = |name: &str| {
+            self.players
+                .get(&key(name))
+                .map_or(INITIAL_RATING, |player| player.rating)
+        };
+        let (first_rating, second_rating) = (current(first), current(second));
+        let expected = 1.0 / (1.0 + 10f64.powf((second_rating - first_rating) / 400.0));
+        let (score, first_result, second_result) = match winner {
+            Some(winner) if key(winner) == key(first) => (1.0, GameResult::Win, GameResult::Loss),
+            Some(_) => (0.0, GameResult::Loss, GameResult::Win),
+            None => (0.5, GameResult::Draw, GameResult::Draw),
+        };
+        let delta = K_FACTOR * (score - expected);
+
+        [
+            (first, second, first_rating, first_result, delta),
+            (second, first, second_rating, second_result, -delta),
+        ]
+        .into_iter()
+        .map(|(name, opponent, before, result, delta)| {
+            let player = self
+                .players
+                .entry(key(name))
+                .or_insert_with(|| Rating::new(name));
+            player.name = name.to_string();
+            player.rating = before + delta;
+            match result {
+                GameResult::Win => player.wins += 1,
+                GameResult::Loss => player.losses += 1,
+                GameResult::Draw => player.draws += 1,
+            }
+            let change = player.rating.round() as i64 - before.round() as i64;
+            if player.recent.len() == RECENT_GAMES {
+                player.recent.pop_back();
+            }
+            player.recent.push_front(RatedGame {
+                game_id: game_id.to_string(),
+                opponent: opponent.to_string(),
+                result,
+                ending,
+                change,
+                at,
+            });
+            RatingChange {
+                name: name.to_string(),
+                rating: player.rating.round() as i64,
+                change,
+            }
+        })
+        .collect()
+    }
+
+    // Highest rating first, ties broken by name
+    fn ranked(&self) -> Vec<&Rating> {
+        let mut ranked: Vec<&Rating> = self.players.values().collect();
+        ranked.sort_by(|a, b| {
+            b.rating
+                .total_cmp(&a.rating)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        ranked
+    }
+
+    /// The `limit` highest-rated players.
+    pub fn leaderboard(&self, limit: usize) -> Vec<PlayerStats> {
+        self.ranked()
+            .into_iter()
+            .take(limit)
+            .enumerate()
+            .map(|(index, player)| stats(index + 1, player, false))
+            .collect()
+    }
+
+    /// A player's standing and recent games, or `None` if they have never played a rated game.
+    pub fn stats(&self, name: &str) -> Option<PlayerStats> {
+        let name = key(name.trim());
+        self.ranked()
+            .into_iter()
+            .enumerate()
+            .find(|(_, player)| key(&player.name) == name)
+            .map(|(index, player)| stats(index + 1, player, true))
+    }
+}
+
+fn stats(rank: usize, player: &Rating, with_recent: bool) -> PlayerStats {
+    PlayerStats {
+        name: player.name.clone(),
+        rank,
+        rating: player.rating.round() as i64,
+        wins: player.wins,
+        losses: player.losses,
+        draws: player.draws,
+        recent: match with_recent {
+            true => player.recent.iter().cloned().collect(),
+            false => Vec::new(),
+        },
+    }
+}
+
+impl fmt::Display for PlayerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}. {} {} | {}W {}L {}D",
+            self.rank, self.name, self.rating, self.wins, self.losses, self.draws
+        )
+    }
+}
+
+impl fmt::Display for RatedGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = match self.result {
+            GameResult::Win => format!("beat {}", self.opponent),
+            GameResult::Loss => format!("lost to {}", self.opponent),
+            GameResult::Draw => format!("drew with {}", self.opponent),
+        };
+        let ending = match (self.ending, self.result) {
+            (Ending::Time, GameResult::Win) => " (opponent ran out of time)",
+            (Ending::Time, _) => " (ran out of time)",
+            (Ending::Abandoned, GameResult::Win) => " (opponent abandoned)",
+            (Ending::Abandoned, _) => " (abandoned)",
+            (Ending::Board, _) => "",
+        };
+        write!(
+            f,
+            "{} {}{} {:+} | game {}",
+            self.at.format("%Y-%m-%d %H:%M"),
+            result,
+            ending,
+            self.change,
+            self.game_id
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(ratings: &mut Ratings, winner: Option<&str>) -> Vec<RatingChange> {
+        ratings.record("g", ["alice", "bob"], winner, Ending::Board, Utc::now())
+    }
+
+    #[test]
+    fn equal_players_trade_half_the_k_factor() {
+        let mut ratings = Ratings::default();
+        let changes = play(&mut ratings, Some("alice"));
+        assert_eq!((changes[0].rating, changes[0].change), (1516, 16));
+        assert_eq!((changes[1].rating, changes[1].change), (1484, -16));
+    }
+
+    #[test]
+    fn a_draw_between_equals_changes_nothing() {
+        let mut ratings = Ratings::default();
+        let changes = play(&mut ratings, None);
+        assert!(changes.iter().all(|change| change.change == 0));
+        assert_eq!(ratings.stats("alice").unwrap().draws, 1);
+    }
+
+    #[test]
+    fn beating_a_weaker_player_gains_less() {
+        let mut ratings = Ratings::default();
+        play(&mut ratings, Some("alice"));
+        let changes = play(&mut ratings, Some("alice"));
+        assert!(changes[0].change > 0 && changes[0].change < 16);
+        // Points only move between the two players
+        assert_eq!(changes[0].rating + changes[1].rating, 3000);
+
+        let upset = play(&mut ratings, Some("bob"));
+        assert!(upset[1].change > 16);
+    }
+
+    #[test]
+    fn names_are_matched_regardless_of_case() {
+        let mut ratings = Ratings::default();
+        play(&mut ratings, Some("ALICE"));
+        let stats = ratings.stats(" Alice ").unwrap();
+        assert_eq!((stats.rating, stats.wins, stats.rank), (1516, 1, 1));
+        assert_eq!(ratings.leaderboard(10)[1].name, "bob");
+        assert_eq!(ratings.len(), 2);
+    }
+}
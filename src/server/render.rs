@@ -0,0 +1,313 @@
+// render.rs
+
+use crate::board::BoardConfig;
+use crate::clock::format_duration;
+use crate::game::{Game, GameOutcome, GameStatus};
+use crate::player::{Player, PlayerSymbol};
+use crate::protocol::{GameSnapshot, LobbyEntry, PlayerInfo};
+
+/// Turns a game into something a client can display. Renderers never change the game.
+pub trait Renderer {
+    fn render(&self, game: &Game) -> String;
+}
+
+/// Coloured board with emoji, as shown to text clients.
+pub struct AnsiRenderer;
+
+/// Board without colours or emoji, suitable for logs.
+pub struct PlainRenderer;
+
+/// Structured snapshot serialised as JSON.
+pub struct JsonRenderer;
+
+// Cell and separator styling used when drawing the grid
+struct GridStyle {
+    x: (&'static str, &'static str),
+    o: (&'static str, &'static str),
+    empty: (&'static str, &'static str),
+    line: &'static str,
+}
+
+const ANSI_STYLE: GridStyle = GridStyle {
+    x: ("\x1b[1;31m", "\x1b[0m"),           // Bold red for 'X'
+    o: ("\x1b[1;34m", "\x1b[0m"),           // Bold blue for 'O'
+    empty: ("\x1b[90m", "\x1b[0m"),         // Light gray for numbers
+    line: "\x1b[1;30m═══╬═══╬═══\x1b[0m\n", // Dark line separator
+};
+
+const PLAIN_STYLE: GridStyle = GridStyle {
+    x: ("", ""),
+    o: ("", ""),
+    empty: ("", ""),
+    line: "═══╬═══╬═══\n",
+};
+
+fn render_grid(game: &Game, style: &GridStyle) -> String {
+    if !game.config.is_classic() {
+        return render_coordinate_grid(game, style);
+    }
+    let mut board_state = String::new();
+
+    // Build the 3x3 grid
+    for row in 0..3 {
+        for col in 0..3 {
+            let index = row * 3 + col;
+            let symbol = match game.board[index] {
+                Some(PlayerSymbol::X) => format!("{} X {}", style.x.0, style.x.1),
+                Some(PlayerSymbol::O) => format!("{} O {}", style.o.0, style.o.1),
+                None => format!("{} {} {}", style.empty.0, index + 1, style.empty.1),
+            };
+            board_state.push_str(&symbol);
+            if col < 2 {
+                board_state.push('║'); // Vertical separator
+            }
+        }
+        board_state.push('\n'); // Move to the next row
+        if row < 2 {
+            board_state.push_str(style.line); // Dark separator
+        }
+    }
+    board_state
+}
+
+// Larger boards are labelled with column letters and row numbers, e.g. `c3`
+fn render_coordinate_grid(game: &Game, style: &GridStyle) -> String {
+    let config = &game.config;
+    let mut board_state = String::from("   ");
+    for col in 0..config.cols {
+        board_state.push_str(&format!(" {} ", BoardConfig::column_name(col)));
+    }
+    board_state.push('\n');
+
+    for row in 0..config.rows {
+        board_state.push_str(&format!("{:>2} ", row + 1));
+        for col in 0..config.cols {
+            let symbol = match game.board[row * config.cols + col] {
+                Some(PlayerSymbol::X) => format!("{} X {}", style.x.0, style.x.1),
+                Some(PlayerSymbol::O) => format!("{} O {}", style.o.0, style.o.1),
+                None => format!("{} · {}", style.empty.0, style.empty.1),
+            };
+            board_state.push_str(&symbol);
+        }
+        board_state.push('\n');
+    }
+    board_state
+}
+
+/// Just the coloured grid, without the game header or turn line.
+pub fn render_board(game: &Game) -> String {
+    render_grid(game, &ANSI_STYLE)
+}
+
+fn symbol_char(symbol: &PlayerSymbol) -> char {
+    match symbol {
+        PlayerSymbol::X => 'X',
+        PlayerSymbol::O => 'O',
+    }
+}
+
+// Running score once both seats are taken, e.g. `alice 2 - 1 bob, 1 draw | round 4 of 5`
+fn series_summary(game: &Game) -> Option<String> {
+    let [first, second] = game.players.as_slice() else {
+        return None;
+    };
+    let draws = match game.draws {
+        0 => String::new(),
+        1 => ", 1 draw".to_string(),
+        draws => format!(", {} draws", draws),
+    };
+    let round = match game.best_of {
+        Some(best_of) => format!("round {} of {}", game.round, best_of),
+        None => format!("round {}", game.round),
+    };
+    Some(format!(
+        "{} {} - {} {}{} | {}",
+        first.get_name(),
+        first.get_wins(),
+        second.get_wins(),
+        second.get_name(),
+        draws,
+        round
+    ))
+}
+
+// Time each player has left, e.g. `alice 4:58 | bob 5:00 (5:00+3)`
+fn clock_summary(game: &Game) -> Option<String> {
+    if game.players.len() < 2 {
+        return None;
+    }
+    let times = game
+        .players
+        .iter()
+        .enumerate()
+        .map(|(seat, player)| {
+            let left = game.clock.time_left(seat)?;
+            Some(format!("{} {}", player.get_name(), format_duration(left)))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("{} ({})", times.join(" | "), game.clock.control))
+}
+
+fn winner_name(game: &Game, symbol: &PlayerSymbol) -> String {
+    game.player_with_symbol(symbol)
+        .map(|p| p.get_name())
+        .unwrap_or_default()
+}
+
+impl Renderer for AnsiRenderer {
+    fn render(&self, game: &Game) -> String {
+        let board_state = render_grid(game, &ANSI_STYLE);
+        let summary = match game.outcome() {
+            GameOutcome::Draw => "🌟 Result: Draw!! 🎉".to_string(),
+            GameOutcome::WonOnTime { symbol } => format!(
+                "⌛ Winner on time: {} ({}) 🎉",
+                winner_name(game, &symbol),
+                symbol_char(&symbol)
+            ),
+            GameOutcome::Win { symbol, .. } => format!(
+                "🏆 Winner: {} ({}) 🎉",
+                winner_name(game, &symbol),
+                symbol_char(&symbol)
+            ),
+            GameOutcome::Ongoing => match game.current_player() {
+                Some(player) if game.status != GameStatus::WaitingForPlayers => format!(
+                    "🌟 It's {} ({})'s turn! ({})",
+                    player.get_name(),
+                    symbol_char(&player.get_symbol()),
+                    game.config.move_hint()
+                ),
+                _ => "⏳ Waiting for players to join the game...".to_string(),
+            },
+        };
+        let series = match series_summary(game) {
+            Some(series) => format!("\n🏅 Series: {}", series),
+            None => String::new(),
+        };
+        let clock = match clock_summary(game) {
+            Some(clock) => format!("\n⏱️ Clock: {}", clock),
+            None => String::new(),
+        };
+        let watching = match game.spectator_count() {
+            0 => String::new(),
+            count => format!("\n👀 {} watching", count),
+        };
+        format!(
+            "🎮 Game ID: {} | 📐 {}\n\n{}\n{}{}{}{}",
+            game.id, game.config, board_state, summary, series, clock, watching
+        )
+    }
+}
+
+impl Renderer for PlainRenderer {
+    fn render(&self, game: &Game) -> String {
+        let board_state = render_grid(game, &PLAIN_STYLE);
+        let summary = match game.outcome() {
+            GameOutcome::Draw => "Result: draw".to_string(),
+            GameOutcome::WonOnTime { symbol } => format!(
+                "Winner on time: {} ({})",
+                winner_name(game, &symbol),
+                symbol_char(&symbol)
+            ),
+            GameOutcome::Win { symbol, .. } => format!(
+                "Winner: {} ({})",
+                winner_name(game, &symbol),
+                symbol_char(&symbol)
+            ),
+            GameOutcome::Ongoing => match game.current_player() {
+                Some(player) if game.status != GameStatus::WaitingForPlayers => format!(
+                    "Turn: {} ({})",
+                    player.get_name(),
+                    symbol_char(&player.get_symbol())
+                ),
+                _ => "Waiting for players".to_string(),
+            },
+        };
+        let series = match series_summary(game) {
+            Some(series) => format!("\nSeries: {}", series),
+            None => String::new(),
+        };
+        let clock = match clock_summary(game) {
+            Some(clock) => format!("\nClock: {}", clock),
+            None => String::new(),
+        };
+        format!(
+            "Game ID: {} ({})\n{}{}{}{}",
+            game.id, game.config, board_state, summary, series, clock
+        )
+    }
+}
+
+impl JsonRenderer {
+    pub fn snapshot(game: &Game) -> GameSnapshot {
+        let player_info = |player: &Player| PlayerInfo {
+            name: player.get_name(),
+            symbol: player.get_symbol(),
+            bot: player.is_bot(),
+            connected: player.is_connected(),
+            wins: player.get_wins(),
+            time_left_ms: game
+                .players
+                .iter()
+                .position(|p| p.get_id() == player.get_id())
+                .and_then(|seat| game.clock.time_left(seat))
+                .map(|left| left.as_millis() as u64),
+        };
+        let turn = match game.status {
+            GameStatus::InProgress => game.current_player().map(player_info),
+            _ => None,
+        };
+        let (winner, winning_line, draw) = match game.outcome() {
+            GameOutcome::Win { symbol, line } => (
+                game.player_with_symbol(&symbol).map(player_info),
+                Some(line),
+                false,
+            ),
+            GameOutcome::WonOnTime { symbol } => (
+                game.player_with_symbol(&symbol).map(player_info),
+                None,
+                false,
+            ),
+            GameOutcome::Draw => (None, None, true),
+            GameOutcome::Ongoing => (None, None, false),
+        };
+        GameSnapshot {
+            game_id: game.id.clone(),
+            config: game.config,
+            board: game.board.clone(),
+            players: game.players.iter().map(player_info).collect(),
+            spectators: game.spectator_count(),
+            status: game.status.clone(),
+            turn,
+            winner,
+            winning_line,
+            draw,
+            round: game.round,
+            draws: game.draws,
+            best_of: game.best_of,
+            match_over: game.match_over(),
+            time_control: game.clock.control,
+            won_on_time: game.won_on_time.is_some(),
+        }
+    }
+
+    pub fn lobby_entry(game: &Game) -> LobbyEntry {
+        LobbyEntry {
+            game_id: game.id.clone(),
+            creator: game.creator.clone(),
+            config: game.config,
+            status: game.status.clone(),
+            players: game.players.len(),
+            spectators: game.spectator_count(),
+            bot: game.players.iter().any(|p| p.is_bot()),
+            best_of: game.best_of,
+            time_control: game.clock.control,
+            age_secs: game.created_at.elapsed().as_secs(),
+        }
+    }
+}
+
+impl Renderer for JsonRenderer {
+    fn render(&self, game: &Game) -> String {
+        serde_json::to_string(&Self::snapshot(game)).unwrap_or_default()
+    }
+}
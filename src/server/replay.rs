@@ -0,0 +1,145 @@
+// replay.rs
+
+use crate::board::BoardConfig;
+use crate::game::{Game, GameOutcome, GameStatus, MoveRecord};
+use crate::player::{Player, PlayerSymbol};
+use crate::render::render_board;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayPlayer {
+    pub name: String,
+    pub symbol: PlayerSymbol,
+}
+
+/// A finished round as exported for replay: who played, every move in order and the result.
+/// `notation` is the compact form of the moves, e.g. `X:5 O:1 X:9`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Replay {
+    pub game_id: String,
+    #[serde(flatten)]
+    pub config: BoardConfig,
+    pub players: Vec<ReplayPlayer>,
+    pub moves: Vec<MoveRecord>,
+    // `None` for a draw
+    pub winner: Option<PlayerSymbol>,
+    // The loser ran out of time rather than being beaten on the board
+    pub won_on_time: bool,
+    pub notation: String,
+}
+
+/// One move of a replay as shown to a text client stepping through it.
+#[derive(Debug, Clone)]
+pub struct ReplayStep {
+    pub number: usize,
+    pub total: usize,
+    pub record: MoveRecord,
+    // The cell as text clients name it, e.g. `5` or `c3`
+    pub cell: String,
+    pub board: String,
+    // Filled in on the last move
+    pub result: Option<String>,
+}
+
+impl Replay {
+    pub fn from_game(game: &Game) -> Self {
+        let winner = match game.outcome() {
+            GameOutcome::Win { symbol, .. } | GameOutcome::WonOnTime { symbol } => Some(symbol),
+            _ => None,
+        };
+        Replay {
+            game_id: game.get_id(),
+            config: game.config,
+            players: game
+                .players
+                .iter()
+                .map(|player| ReplayPlayer {
+                    name: player.get_name(),
+                    symbol: player.get_symbol(),
+                })
+                .collect(),
+            moves: game.moves.clone(),
+            winner,
+            won_on_time: game.won_on_time.is_some(),
+            notation: notation(&game.config, &game.moves),
+        }
+    }
+
+    /// The game as it stood after its first `count` moves.
+    pub fn position(&self, count: usize) -> Game {
+        let mut game = Game::with_id(self.game_id.clone(), String::new(), self.config);
+        for player in &self.players {
+            game.add_player(Player::reserved(
+                player.name.clone(),
+                player.symbol.clone(),
+                String::new(),
+            ));
+        }
+        game.set_status(GameStatus::InProgress);
+        for record in self.moves.iter().take(count) {
+            game.replay_move(record.clone());
+        }
+        game
+    }
+
+    /// Move `number`, counting from 1, with the board as it stood afterwards.
+    pub fn step(&self, number: usize) -> Option<ReplayStep> {
+        let record = self.moves.get(number.checked_sub(1)?)?.clone();
+        let total = self.moves.len();
+        Some(ReplayStep {
+            number,
+            total,
+            cell: self.config.cell_name(record.cell),
+            record,
+            board: render_board(&self.position(number)),
+            result: (number == total).then(|| self.result()),
+        })
+    }
+
+    pub fn result(&self) -> String {
+        match &self.winner {
+            Some(symbol) if self.won_on_time => {
+                format!("{} ({}) won on time", self.player_name(symbol), symbol)
+            }
+            Some(symbol) => format!("{} ({}) won", self.player_name(symbol), symbol),
+            None => "Draw".to_string(),
+        }
+    }
+
+    fn player_name(&self, symbol: &PlayerSymbol) -> String {
+        self.players
+            .iter()
+            .find(|player| &player.symbol == symbol)
+            .map(|player| player.name.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let players: Vec<String> = self
+            .players
+            .iter()
+            .map(|player| format!("{} ({})", player.name, player.symbol))
+            .collect();
+        write!(
+            f,
+            "Game {} | {} | {} | {} moves | {}",
+            self.game_id,
+            self.config,
+            players.join(" vs "),
+            self.moves.len(),
+            self.result()
+        )
+    }
+}
+
+/// Writes moves as `SYMBOL:CELL` pairs, naming cells the way text clients enter them.
+pub fn notation(config: &BoardConfig, moves: &[MoveRecord]) -> String {
+    moves
+        .iter()
+        .map(|record| format!("{}:{}", record.symbol, config.cell_name(record.cell)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,117 +1,763 @@
 // server.rs
 
-use crate::game::{Game, GameStatus};
-use crate::player::Player;
-use crate::player::PlayerSymbol;
-use log::info;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, Duration};
+use crate::accounts::{Accounts, AuthError};
+use crate::actor::{self, GameCommand, GameHandle};
+use crate::board::BoardConfig;
+use crate::bot::Difficulty;
+use crate::clock::{Clock, TimeControl};
+use crate::config::ServerConfig;
+use crate::game::{Game, GameError, GameSettings, GameStatus};
+use crate::limits::Connections;
+use crate::metrics::Metrics;
+use crate::names::NamePolicy;
+use crate::outbox::Outbox;
+use crate::player::{Player, PlayerSymbol};
+use crate::protocol::{GameMessage, GameSnapshot, LobbyEntry, Protocol};
+use crate::rating::{Ending, PlayerStats, RatingChange, Ratings};
+use crate::render::JsonRenderer;
+use crate::replay::Replay;
+use crate::storage::{self, GameEvent, GameRecord, GameStore};
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use log::{error, info, warn};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::time::sleep;
 
+// How often a shutdown checks whether the games have finished
+const SHUTDOWN_POLL: Duration = Duration::from_millis(250);
+
+/// Narrows the lobby listing. Finished games are never listed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LobbyFilter {
+    // Only games with a seat free for a new player
+    pub open_only: bool,
+    pub board: Option<BoardConfig>,
+}
+
+impl LobbyFilter {
+    pub fn matches(&self, entry: &LobbyEntry) -> bool {
+        match entry.status {
+            GameStatus::Finished => false,
+            _ if self.open_only && !entry.is_open() => false,
+            _ => self.board.is_none_or(|board| board == entry.config),
+        }
+    }
+}
+
+// A connection at the menu that wants the lobby re-sent whenever it changes
+#[derive(Debug)]
+struct LobbySubscriber {
+    outbox: Outbox,
+    protocol: Protocol,
+    filter: LobbyFilter,
+}
+
+// A connection signed in under a name
+#[derive(Debug)]
+struct Session {
+    connection_id: String,
+    outbox: Outbox,
+    protocol: Protocol,
+    registered: bool,
+}
+
+// A game's lobby row as its task last reported it
+#[derive(Debug)]
+struct Listing {
+    entry: LobbyEntry,
+    created_at: Instant,
+}
+
+/// Registry of running games. Each game is owned by its own task (see `actor`), so the
+/// registry only holds handles and lobby rows, and no lock is held while a game runs.
 #[derive(Debug)]
 pub struct GameServer {
-    games: HashMap<String, Arc<Mutex<Game>>>,
+    config: ServerConfig,
+    games: DashMap<String, GameHandle>,
+    listings: DashMap<String, Listing>,
+    // Resume token to the id of the game holding that seat
+    resume_tokens: DashMap<String, String>,
+    lobby_subscribers: DashMap<String, LobbySubscriber>,
+    store: Box<dyn GameStore>,
+    ratings: Mutex<Ratings>,
+    accounts: Accounts,
+    names: NamePolicy,
+    // Lowercased name of everyone connected, so no two connections share a name
+    sessions: DashMap<String, Session>,
+    connections: Arc<Connections>,
+    metrics: Metrics,
+    // Taking new connections; `/readyz` reports this
+    ready: AtomicBool,
+    // Shutting down, so no new games start
+    draining: AtomicBool,
+    // Set once the shutdown's grace period is over, ending every connection
+    closing: watch::Sender<bool>,
 }
 
 impl GameServer {
-    pub fn new() -> Self {
+    pub fn new(
+        config: ServerConfig,
+        store: Box<dyn GameStore>,
+        accounts: Accounts,
+        names: NamePolicy,
+    ) -> Self {
+        let connections = Connections::new(config.max_connections, config.max_connections_per_ip);
         GameServer {
-            games: HashMap::new(),
+            config,
+            store,
+            accounts,
+            names,
+            sessions: DashMap::new(),
+            connections: Arc::new(connections),
+            metrics: Metrics::default(),
+            ready: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            closing: watch::Sender::new(false),
+            games: DashMap::new(),
+            listings: DashMap::new(),
+            resume_tokens: DashMap::new(),
+            lobby_subscribers: DashMap::new(),
+            ratings: Mutex::new(Ratings::default()),
+        }
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// The global and per-address connection limits.
+    pub fn connections(&self) -> &Arc<Connections> {
+        &self.connections
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// The metrics in the Prometheus text format, with the game counts brought up to date.
+    pub fn export_metrics(&self) -> String {
+        self.metrics.set_games(&self.count_games());
+        self.metrics.encode()
+    }
+
+    fn count_games(&self) -> [(GameStatus, usize); 3] {
+        let mut counts = [
+            (GameStatus::WaitingForPlayers, 0),
+            (GameStatus::InProgress, 0),
+            (GameStatus::Finished, 0),
+        ];
+        for listing in self.listings.iter() {
+            if let Some((_, count)) = counts
+                .iter_mut()
+                .find(|(status, _)| *status == listing.entry.status)
+            {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// What new names are checked against.
+    pub fn names(&self) -> &NamePolicy {
+        &self.names
+    }
+
+    /// Claims `name` for a connection. Signing in to an account takes the name over from
+    /// the account's older connection, which is closed, so a dropped connection nobody
+    /// noticed can't lock its owner out; a guest has to wait for it to go.
+    pub fn sign_in(
+        &self,
+        name: &str,
+        registered: bool,
+        connection_id: &str,
+        outbox: &Outbox,
+        protocol: Protocol,
+    ) -> Result<(), AuthError> {
+        let session = Session {
+            connection_id: connection_id.to_string(),
+            outbox: outbox.clone(),
+            protocol,
+            registered,
+        };
+        match self.sessions.entry(name.to_lowercase()) {
+            // Held while the connection registers the name
+            Entry::Occupied(mut entry) if entry.get().connection_id == connection_id => {
+                entry.insert(session);
+                Ok(())
+            }
+            Entry::Occupied(mut entry) if registered && entry.get().registered => {
+                let previous = entry.insert(session);
+                info!("{} signed in again, closing their older connection", name);
+                let message = GameMessage::SignedInElsewhere;
+                previous
+                    .outbox
+                    .send(previous.protocol.encode(&message), &message);
+                previous.outbox.close();
+                Ok(())
+            }
+            Entry::Occupied(_) => Err(AuthError::InUse(name.to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(session);
+                Ok(())
+            }
         }
     }
 
-    pub fn get_mut_game(&mut self, id: &String) -> Option<&mut Arc<Mutex<Game>>> {
-        self.games.get_mut(id)
+    /// Frees `name` when its connection ends, unless another connection has taken it over.
+    pub fn sign_out(&self, name: &str, connection_id: &str) {
+        self.sessions.remove_if(&name.to_lowercase(), |_, session| {
+            session.connection_id == connection_id
+        });
+    }
+
+    pub fn get_game(&self, id: &str) -> Option<GameHandle> {
+        self.games.get(id).map(|game| game.clone())
     }
 
-    pub fn get_game(&self, id: &String) -> Option<&Arc<Mutex<Game>>> {
-        self.games.get(id)
+    fn check_capacity(&self) -> Result<(), GameError> {
+        if self.is_draining() {
+            return Err(GameError::ShuttingDown);
+        }
+        if self.games.len() >= self.config.max_games {
+            return Err(GameError::TooManyGames);
+        }
+        Ok(())
+    }
+
+    pub fn create_game(
+        self: &Arc<Self>,
+        player: Player,
+        settings: GameSettings,
+    ) -> Result<String, GameError> {
+        self.check_capacity()?;
+        let mut game = Game::new(player, settings.board);
+        game.best_of = settings.best_of;
+        game.clock = Clock::new(settings.time_control.unwrap_or(self.default_time_control()));
+        Ok(self.start_game(game))
+    }
+
+    pub fn create_bot_game(
+        self: &Arc<Self>,
+        player: Player,
+        difficulty: Difficulty,
+    ) -> Result<String, GameError> {
+        self.check_capacity()?;
+        // Computer opponents play the classic board
+        let mut game = Game::new(player, BoardConfig::default());
+        game.clock = Clock::new(self.default_time_control());
+        actor::seat_player(&mut game, Player::bot(difficulty, PlayerSymbol::O));
+        Ok(self.start_game(game))
+    }
+
+    // Games created without a time control give each move the configured turn timeout,
+    // or no limit if it is 0
+    fn default_time_control(&self) -> TimeControl {
+        match self.config.turn_timeout.as_secs() {
+            0 => TimeControl::default(),
+            secs => TimeControl::per_move(secs),
+        }
     }
 
-    pub fn create_game(&mut self, player: Player) -> String {
-        let game = Game::new(player);
+    /// Reloads the games that were unfinished when the server last stopped, with their
+    /// seats reserved for the players' resume tokens, and everyone's ratings. Returns how
+    /// many games were restored.
+    pub fn restore_games(self: &Arc<Self>) -> io::Result<usize> {
+        let records = self.store.load()?;
+        let ratings = storage::ratings(&records);
+        if !ratings.is_empty() {
+            info!("Loaded ratings for {} players", ratings.len());
+        }
+        *self.ratings() = ratings;
+        let games = storage::restore(&records);
+        let count = games.len();
+        for game in games {
+            self.launch_game(game);
+        }
+        Ok(count)
+    }
+
+    // Records a new game and starts it
+    fn start_game(self: &Arc<Self>, game: Game) -> String {
         let game_id = game.get_id();
-        self.games
-            .insert(game_id.clone(), Arc::new(Mutex::new(game)));
+        self.record(
+            &game_id,
+            GameEvent::Created {
+                creator: game.creator.clone(),
+                board: game.config,
+                best_of: game.best_of,
+                time_control: Some(game.clock.control),
+            },
+        );
+        for player in game.players.iter() {
+            self.record(&game_id, GameEvent::seated(player));
+        }
+        self.launch_game(game);
         game_id
     }
 
-    pub async fn join_game(&mut self, mut player: Player) -> Result<String, String> {
-        // Try to find an open game
-        for (game_id, game_arc) in self.games.iter_mut() {
-            let mut game = game_arc.lock().await;
-
-            // Only allow joining if game is in "WaitingForPlayers" state and has space for one more player
-            if game.get_status() == GameStatus::WaitingForPlayers && game.get_players().len() < 2 {
-                let player_name = player.get_name();
-                
-                // Determine which symbol the player will get
-                if let Some(game_player) = game.get_players().first() {
-                    if game_player.get_symbol() == PlayerSymbol::O {
-                        player.set_symbol(PlayerSymbol::X);
-                    } else {
-                        player.set_symbol(PlayerSymbol::O);
-                    }
-                } else {
-                    player.set_symbol(PlayerSymbol::O); // First player always gets 'O'
-                }
+    // Hands a game to its own task and lists it
+    fn launch_game(self: &Arc<Self>, game: Game) {
+        let game_id = game.get_id();
+        for player in game.players.iter().filter(|p| !p.is_bot()) {
+            self.register_token(&player.get_resume_token(), &game_id);
+        }
+        let listing = Listing {
+            entry: JsonRenderer::lobby_entry(&game),
+            created_at: game.created_at,
+        };
+        self.games
+            .insert(game_id.clone(), actor::spawn(game, self.clone()));
+        self.listings.insert(game_id, listing);
+        self.publish_lobby();
+    }
 
-                // Add the player to the game
-                game.add_player(player.clone());
-                game.broadcast_to_players(format!(
-                    "Player {} has joined the game!\n",
-                    player_name
-                ))
-                .await;
-
-                if game.get_players().len() == 2 {
-                    game.set_status(GameStatus::InProgress);
-                    let game_state = game.get_game_state();
-                    game.broadcast_to_players(game_state).await;
-                }
+    /// Adds an event to the game's stored history. A failed write is logged rather than
+    /// interrupting the game.
+    pub fn record(&self, game_id: &str, event: GameEvent) {
+        if let Err(e) = self.store.append(&GameRecord::new(game_id, event)) {
+            error!("Failed to record an event for game {}: {}", game_id, e);
+        }
+    }
 
-                return Ok(game_id.clone()); // Return the game ID of the game the player joined
+    /// Waits for the game store to write everything recorded so far. Blocks, so call it
+    /// off the async runtime.
+    pub fn flush_store(&self) {
+        if let Err(e) = self.store.flush() {
+            error!("Failed to write the last game records: {}", e);
+        }
+    }
+
+    fn ratings(&self) -> std::sync::MutexGuard<'_, Ratings> {
+        self.ratings
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Updates both players' ratings after a game between them and records it.
+    pub fn rate(
+        &self,
+        game_id: &str,
+        players: [String; 2],
+        winner: Option<String>,
+        ending: Ending,
+    ) -> Vec<RatingChange> {
+        let [first, second] = &players;
+        let changes = self.ratings().record(
+            game_id,
+            [first, second],
+            winner.as_deref(),
+            ending,
+            Utc::now(),
+        );
+        self.record(
+            game_id,
+            GameEvent::Rated {
+                players,
+                winner,
+                ending,
+            },
+        );
+        changes
+    }
+
+    pub fn leaderboard(&self, limit: usize) -> Vec<PlayerStats> {
+        self.ratings().leaderboard(limit)
+    }
+
+    pub fn stats(&self, name: &str) -> Result<PlayerStats, GameError> {
+        self.ratings()
+            .stats(name)
+            .ok_or_else(|| GameError::NotRated(name.trim().to_string()))
+    }
+
+    /// The latest finished round of a game, live or long closed, read from the game store.
+    /// Reading through the whole store is slow, so it runs off the async runtime.
+    pub async fn replay(self: &Arc<Self>, game_id: &str) -> Result<Replay, GameError> {
+        let server = self.clone();
+        let id = game_id.to_string();
+        tokio::task::spawn_blocking(move || server.read_replay(&id))
+            .await
+            .unwrap_or_else(|_| Err(GameError::NoReplay(game_id.to_string())))
+    }
+
+    fn read_replay(&self, game_id: &str) -> Result<Replay, GameError> {
+        let records = self.store.load().map_err(|e| {
+            error!("Failed to read the game store for a replay: {}", e);
+            GameError::NoReplay(game_id.to_string())
+        })?;
+        if !records.iter().any(|record| record.game_id == game_id) {
+            return Err(GameError::NotFound(game_id.to_string()));
+        }
+        storage::replay(&records, game_id).ok_or_else(|| GameError::NoReplay(game_id.to_string()))
+    }
+
+    pub async fn join_game(&self, game_id: &str, player: Player) -> Result<String, GameError> {
+        // Taking the second seat would start a new game
+        if self.is_draining() {
+            return Err(GameError::ShuttingDown);
+        }
+        let game = self
+            .get_game(game_id)
+            .ok_or_else(|| GameError::NotFound(game_id.to_string()))?;
+        let token = player.get_resume_token();
+        match game
+            .request(|reply| GameCommand::Join { player, reply })
+            .await
+        {
+            Some(Ok(())) => {
+                self.register_token(&token, game_id);
+                Ok(game_id.to_string())
             }
+            Some(Err(e)) => Err(e),
+            // The game closed before the request reached it
+            None => Err(GameError::NotFound(game_id.to_string())),
         }
+    }
 
+    /// Gives a held seat back to a reconnecting player and re-sends the board to it.
+    pub async fn resume_game(
+        &self,
+        token: &str,
+        name: &str,
+        outbox: Outbox,
+        protocol: Protocol,
+    ) -> Result<(String, Player), GameError> {
+        let game_id = self
+            .resume_tokens
+            .get(token)
+            .map(|game_id| game_id.clone())
+            .ok_or(GameError::InvalidResumeToken)?;
+        let game = self
+            .get_game(&game_id)
+            .ok_or(GameError::InvalidResumeToken)?;
+        let token = token.to_string();
+        let name = name.to_string();
+        let player = game
+            .request(|reply| GameCommand::Resume {
+                token,
+                name,
+                outbox,
+                protocol,
+                reply,
+            })
+            .await
+            .unwrap_or(Err(GameError::InvalidResumeToken))?;
+        Ok((game_id, player))
+    }
+
+    /// Every game, finished ones included, oldest first.
+    pub fn list_games(&self) -> Vec<LobbyEntry> {
+        let mut listings: Vec<(Instant, LobbyEntry)> = self
+            .listings
+            .iter()
+            .map(|listing| {
+                let entry = LobbyEntry {
+                    age_secs: listing.created_at.elapsed().as_secs(),
+                    ..listing.entry.clone()
+                };
+                (listing.created_at, entry)
+            })
+            .collect();
+        listings.sort_by_key(|(created_at, _)| *created_at);
+        listings.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    /// A running game's board, players and status.
+    pub async fn inspect_game(&self, game_id: &str) -> Result<GameSnapshot, GameError> {
+        let not_found = || GameError::NotFound(game_id.to_string());
+        let game = self.get_game(game_id).ok_or_else(not_found)?;
+        game.request(|reply| GameCommand::Snapshot { reply })
+            .await
+            .ok_or_else(not_found)
+    }
+
+    /// Closes a game for everyone in it, telling them why if a reason is given.
+    pub async fn close_game(&self, game_id: &str, reason: Option<String>) -> Result<(), GameError> {
+        let not_found = || GameError::NotFound(game_id.to_string());
+        let game = self.get_game(game_id).ok_or_else(not_found)?;
+        game.request(|reply| GameCommand::Close { reason, reply })
+            .await
+            .ok_or_else(not_found)
+    }
+
+    /// Sends an announcement to every signed-in connection and returns how many it reached.
+    pub fn announce(&self, text: &str) -> usize {
+        let message = GameMessage::Announcement(text.to_string());
+        self.sessions
+            .iter()
+            .filter(|session| {
+                let frame = session.protocol.encode(&message);
+                session.outbox.broadcast(frame, &message)
+            })
+            .count()
+    }
+
+    pub async fn watch_game(&self, game_id: &str, spectator: Player) -> Result<(), GameError> {
+        let not_found = || GameError::NotFound(game_id.to_string());
+        let game = self.get_game(game_id).ok_or_else(not_found)?;
+        game.request(|reply| GameCommand::Watch { spectator, reply })
+            .await
+            .ok_or_else(not_found)
+    }
+
+    pub async fn quick_match(&self, player: Player) -> Result<String, GameError> {
+        // Oldest open games first. Another player may take a seat before our request
+        // reaches its game, in which case the next one is tried.
+        let mut open: Vec<(String, Instant)> = self
+            .listings
+            .iter()
+            .filter(|listing| listing.entry.is_open())
+            .map(|listing| (listing.key().clone(), listing.created_at))
+            .collect();
+        open.sort_by_key(|(_, created_at)| *created_at);
+
+        for (game_id, _) in open {
+            if let Ok(game_id) = self.join_game(&game_id, player.clone()).await {
+                return Ok(game_id);
+            }
+        }
         // If no available games
-        Err("Couldn't find any games available, please try again or create a new one".to_string())
+        Err(GameError::NoOpenGames)
+    }
+
+    pub fn register_token(&self, token: &str, game_id: &str) {
+        self.resume_tokens
+            .insert(token.to_string(), game_id.to_string());
+    }
+
+    pub fn forget_token(&self, token: &str) {
+        self.resume_tokens.remove(token);
     }
 
-    pub async fn remove_game(&mut self, game_id: &str) {
+    /// Refreshes a game's lobby row and re-sends the lobby. Called by the game's task.
+    pub fn update_listing(&self, game: &Game) {
+        self.listings.insert(
+            game.get_id(),
+            Listing {
+                entry: JsonRenderer::lobby_entry(game),
+                created_at: game.created_at,
+            },
+        );
+        self.publish_lobby();
+    }
+
+    pub fn remove_game(&self, game_id: &str) {
+        self.record(game_id, GameEvent::Closed);
         self.games.remove(game_id);
+        self.listings.remove(game_id);
+        self.resume_tokens.retain(|_, id| id != game_id);
+        self.publish_lobby();
+    }
+
+    pub fn lobby(&self, filter: &LobbyFilter) -> Vec<LobbyEntry> {
+        let mut entries: Vec<LobbyEntry> = self
+            .listings
+            .iter()
+            .filter(|listing| filter.matches(&listing.entry))
+            .map(|listing| LobbyEntry {
+                age_secs: listing.created_at.elapsed().as_secs(),
+                ..listing.entry.clone()
+            })
+            .collect();
+        // Newest games first
+        entries.sort_by_key(|entry| entry.age_secs);
+        entries
     }
 
-    pub fn start_logging_active_games(server: Arc<Mutex<Self>>) {
+    pub fn subscribe_lobby(
+        &self,
+        subscriber_id: &str,
+        outbox: Outbox,
+        protocol: Protocol,
+        filter: LobbyFilter,
+    ) {
+        self.lobby_subscribers.insert(
+            subscriber_id.to_string(),
+            LobbySubscriber {
+                outbox,
+                protocol,
+                filter,
+            },
+        );
+    }
+
+    pub fn unsubscribe_lobby(&self, subscriber_id: &str) -> bool {
+        self.lobby_subscribers.remove(subscriber_id).is_some()
+    }
+
+    /// Sends the current lobby to every subscriber. Call this after games are added,
+    /// removed or change status.
+    pub fn publish_lobby(&self) {
+        if self.lobby_subscribers.is_empty() {
+            return;
+        }
+        let mut gone = Vec::new();
+        for subscriber in self.lobby_subscribers.iter() {
+            let message = GameMessage::Lobby(self.lobby(&subscriber.filter));
+            let frame = subscriber.protocol.encode_broadcast(&message);
+            if !subscriber.outbox.broadcast(frame, &message) {
+                gone.push(subscriber.key().clone());
+            }
+        }
+        for subscriber_id in gone {
+            self.lobby_subscribers.remove(&subscriber_id);
+        }
+    }
+
+    /// Stops the server's games. Everyone in a game is warned, then games still being
+    /// played after `grace` are stopped, and finally every connection is told to close.
+    pub async fn shutdown(&self, grace: Duration) {
+        self.set_ready(false);
+        self.draining.store(true, Ordering::SeqCst);
+        for game in self.games.iter() {
+            game.send(GameCommand::ShuttingDown {
+                seconds: grace.as_secs(),
+            });
+        }
+
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            let [_, (_, playing), _] = self.count_games();
+            if playing == 0 {
+                break;
+            }
+            sleep(SHUTDOWN_POLL).await;
+        }
+
+        let games: Vec<GameHandle> = self.games.iter().map(|game| game.clone()).collect();
+        let mut stopped = 0;
+        for game in games {
+            if let Some(true) = game.request(|reply| GameCommand::Abort { reply }).await {
+                stopped += 1;
+            }
+        }
+        if stopped > 0 {
+            warn!("Stopped {} games that were still being played", stopped);
+        }
+        self.closing.send_replace(true);
+    }
+
+    /// Resolves once the shutdown has ended its games and connections should close.
+    pub async fn closing(&self) {
+        let mut closing = self.closing.subscribe();
+        let _ = closing.wait_for(|closing| *closing).await;
+    }
+
+    /// Logs how many games there are every `log_interval`, listing each one if `log_games`
+    /// is set.
+    pub fn start_logging_active_games(server: Arc<Self>) {
         tokio::spawn(async move {
             loop {
-                sleep(Duration::from_secs(10)).await; // Log every 10 seconds
-
-                let server = server.lock().await;
-                let mut active_games = Vec::new();
-                let games_len = server.games.len();
-                for (game_id, game) in server.games.iter() {
-                    let game = game.lock().await;
-                    let players: Vec<String> = game.players.iter().map(|p| p.get_name()).collect();
-                    active_games.push(format!(
-                        "Game {}: Players [{}] | Status: {:?}",
-                        game_id,
-                        players.join(", "),
-                        game.get_status()
-                    ));
-                }
+                sleep(server.config.log_interval).await;
 
-                if !active_games.is_empty() {
-                    info!(
-                        "🟢 Active Games: {}\n{}",
-                        games_len,
-                        active_games.join("\n")
-                    );
-                } else {
+                let [waiting, playing, finished] = server.count_games().map(|(_, count)| count);
+                if waiting + playing + finished == 0 {
                     info!("⚪ No active games.");
+                    continue;
                 }
+                let summary = format!(
+                    "🟢 Active Games: {} ({} waiting, {} in progress, {} finished)",
+                    waiting + playing + finished,
+                    waiting,
+                    playing,
+                    finished
+                );
+                if !server.config.log_games {
+                    info!("{}", summary);
+                    continue;
+                }
+
+                let active_games: Vec<String> = server
+                    .listings
+                    .iter()
+                    .map(|listing| {
+                        let entry = &listing.entry;
+                        format!(
+                            "Game {}: Created by {} | Players: {} | Spectators: {} | Status: {:?}",
+                            entry.game_id,
+                            entry.creator,
+                            entry.players,
+                            entry.spectators,
+                            entry.status
+                        )
+                    })
+                    .collect();
+                info!("{}\n{}", summary, active_games.join("\n"));
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(status: GameStatus, players: usize, config: BoardConfig) -> LobbyEntry {
+        LobbyEntry {
+            game_id: "g".to_string(),
+            creator: "alice".to_string(),
+            config,
+            status,
+            players,
+            spectators: 0,
+            bot: false,
+            best_of: None,
+            time_control: TimeControl::default(),
+            age_secs: 0,
+        }
+    }
+
+    #[test]
+    fn open_filter_leaves_out_games_with_every_seat_held() {
+        let open = LobbyFilter {
+            open_only: true,
+            board: None,
+        };
+        let classic = BoardConfig::default();
+        assert!(open.matches(&entry(GameStatus::WaitingForPlayers, 1, classic)));
+        // A restored game waits for its players with both seats held
+        assert!(!open.matches(&entry(GameStatus::WaitingForPlayers, 2, classic)));
+        assert!(!open.matches(&entry(GameStatus::InProgress, 2, classic)));
+        assert!(LobbyFilter::default().matches(&entry(GameStatus::WaitingForPlayers, 2, classic)));
+    }
+
+    #[test]
+    fn finished_games_are_never_listed() {
+        let entry = entry(GameStatus::Finished, 2, BoardConfig::default());
+        assert!(!LobbyFilter::default().matches(&entry));
+    }
+
+    #[test]
+    fn board_filter_matches_the_whole_board_shape() {
+        let wide = BoardConfig::new(4, 6, 4).unwrap();
+        let filter = LobbyFilter {
+            open_only: false,
+            board: Some(wide),
+        };
+        assert!(filter.matches(&entry(GameStatus::InProgress, 2, wide)));
+        assert!(!filter.matches(&entry(GameStatus::InProgress, 2, BoardConfig::default())));
+        let shorter_win = BoardConfig::new(4, 6, 3).unwrap();
+        assert!(!filter.matches(&entry(GameStatus::InProgress, 2, shorter_win)));
+    }
+}
@@ -0,0 +1,517 @@
+// storage.rs
+
+use crate::board::BoardConfig;
+use crate::bot::Difficulty;
+use crate::clock::{Clock, TimeControl};
+use crate::game::{Game, GameOutcome, GameStatus, MoveRecord};
+use crate::player::{Player, PlayerSymbol};
+use crate::rating::{Ending, Ratings};
+use crate::replay::Replay;
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Something that happened to a game, as kept by a `GameStore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub game_id: String,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: GameEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    Created {
+        creator: String,
+        board: BoardConfig,
+        #[serde(default)]
+        best_of: Option<usize>,
+        #[serde(default)]
+        time_control: Option<TimeControl>,
+    },
+    // The resume token is kept so the player can reclaim the seat after a restart
+    Seated {
+        name: String,
+        symbol: PlayerSymbol,
+        resume_token: String,
+        bot: Option<Difficulty>,
+        // Signed in to an account; guests' games aren't rated
+        #[serde(default)]
+        registered: bool,
+    },
+    // The move's time is the record's
+    Moved {
+        #[serde(default)]
+        player: String,
+        symbol: PlayerSymbol,
+        cell: usize,
+    },
+    // `None` for a draw
+    Finished {
+        winner: Option<PlayerSymbol>,
+    },
+    // `symbol` ran out of time; followed by the round's `Finished`
+    LostOnTime {
+        symbol: PlayerSymbol,
+    },
+    // Moves undone back to and including `symbol`'s last one
+    TookBack {
+        symbol: PlayerSymbol,
+    },
+    // A round between two people counted towards their ratings; `winner` is `None` for a draw
+    Rated {
+        players: [String; 2],
+        winner: Option<String>,
+        ending: Ending,
+    },
+    Restarted,
+    Left {
+        resume_token: String,
+    },
+    Closed,
+}
+
+impl GameRecord {
+    pub fn new(game_id: &str, event: GameEvent) -> Self {
+        GameRecord {
+            game_id: game_id.to_string(),
+            at: Utc::now(),
+            event,
+        }
+    }
+}
+
+impl GameEvent {
+    pub fn seated(player: &Player) -> Self {
+        GameEvent::Seated {
+            name: player.get_name(),
+            symbol: player.get_symbol(),
+            resume_token: player.get_resume_token(),
+            bot: player.get_bot_difficulty(),
+            registered: player.is_registered(),
+        }
+    }
+
+    pub fn moved(record: &MoveRecord) -> Self {
+        GameEvent::Moved {
+            player: record.player.clone(),
+            symbol: record.symbol.clone(),
+            cell: record.cell,
+        }
+    }
+
+    pub fn finished(outcome: &GameOutcome) -> Self {
+        GameEvent::Finished {
+            winner: outcome.winner().cloned(),
+        }
+    }
+}
+
+/// Where game history is kept. Records are appended as games change and read back
+/// at startup and for replays.
+pub trait GameStore: Send + Sync + fmt::Debug {
+    /// Queues a record without waiting for it to be written, so game tasks can call it.
+    fn append(&self, record: &GameRecord) -> io::Result<()>;
+    fn load(&self) -> io::Result<Vec<GameRecord>>;
+
+    /// Waits until every record appended so far is written. May block.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps records only for as long as the server runs.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: Mutex<Vec<GameRecord>>,
+}
+
+impl GameStore for MemoryStore {
+    fn append(&self, record: &GameRecord) -> io::Result<()> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(record.clone());
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<Vec<GameRecord>> {
+        Ok(self
+            .records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone())
+    }
+}
+
+// Work for a `FileStore`'s writer thread, done in the order it was sent
+#[derive(Debug)]
+enum StoreWrite {
+    Line(String),
+    // Answered once every line sent before it is written
+    Flush(mpsc::SyncSender<io::Result<()>>),
+}
+
+/// Appends each record as a line of JSON to a local file. A thread of its own does the
+/// writing, so a slow disk never holds up a game.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    writer: mpsc::Sender<StoreWrite>,
+}
+
+impl FileStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (writer, writes) = mpsc::channel();
+        let thread_path = path.to_path_buf();
+        thread::Builder::new()
+            .name("game-store".to_string())
+            .spawn(move || write_records(file, writes, &thread_path))?;
+        Ok(FileStore {
+            path: path.to_path_buf(),
+            writer,
+        })
+    }
+}
+
+// Runs until the store is dropped
+fn write_records(mut file: File, writes: mpsc::Receiver<StoreWrite>, path: &Path) {
+    for write in writes {
+        match write {
+            // One write per record so a crash can at worst cut off the last line
+            StoreWrite::Line(line) => {
+                if let Err(e) = file.write_all(line.as_bytes()) {
+                    error!(
+                        "Failed to write to the game store {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            StoreWrite::Flush(done) => {
+                let _ = done.send(file.flush());
+            }
+        }
+    }
+}
+
+fn writer_stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the game store's writer has stopped",
+    )
+}
+
+impl GameStore for FileStore {
+    fn append(&self, record: &GameRecord) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.writer
+            .send(StoreWrite::Line(line))
+            .map_err(|_| writer_stopped())
+    }
+
+    fn load(&self) -> io::Result<Vec<GameRecord>> {
+        // Records still queued would otherwise be missing
+        self.flush()?;
+        let text = std::fs::read_to_string(&self.path)?;
+        let mut records = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!(
+                    "Skipping unreadable record on line {} of {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(records)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let (done, written) = mpsc::sync_channel(1);
+        self.writer
+            .send(StoreWrite::Flush(done))
+            .map_err(|_| writer_stopped())?;
+        written.recv().map_err(|_| writer_stopped())?
+    }
+}
+
+/// Rebuilds the games that hadn't finished, waiting for players, with every human
+/// seat reserved for its player to reclaim with their resume token.
+pub fn restore(records: &[GameRecord]) -> Vec<Game> {
+    rebuild(records, |_| {})
+        .into_iter()
+        .filter(|game| game.get_status() != GameStatus::Finished)
+        .map(|mut game| {
+            game.set_status(GameStatus::WaitingForPlayers);
+            game
+        })
+        .collect()
+}
+
+/// The most recently finished round of a game, or `None` if it never finished one.
+pub fn replay(records: &[GameRecord], game_id: &str) -> Option<Replay> {
+    let mut replay = None;
+    let records = records.iter().filter(|record| record.game_id == game_id);
+    rebuild(records, |game| replay = Some(Replay::from_game(game)));
+    replay
+}
+
+/// Every player's rating, from the rated games in the order they were played.
+pub fn ratings(records: &[GameRecord]) -> Ratings {
+    let mut ratings = Ratings::default();
+    for record in records {
+        if let GameEvent::Rated {
+            players: [first, second],
+            winner,
+            ending,
+        } = &record.event
+        {
+            ratings.record(
+                &record.game_id,
+                [first, second],
+                winner.as_deref(),
+                *ending,
+                record.at,
+            );
+        }
+    }
+    ratings
+}
+
+// Plays the records back into games, calling `finished` as each round finishes.
+// Returns the games that weren't closed, oldest first.
+fn rebuild<'a>(
+    records: impl IntoIterator<Item = &'a GameRecord>,
+    mut finished: impl FnMut(&Game),
+) -> Vec<Game> {
+    let mut games: HashMap<&str, Game> = HashMap::new();
+    // Oldest first, the order the games were created in
+    let mut order = Vec::new();
+
+    for record in records {
+        let game_id = record.game_id.as_str();
+        if let GameEvent::Created {
+            creator,
+            board,
+            best_of,
+            time_control,
+        } = &record.event
+        {
+            let mut game = Game::with_id(game_id.to_string(), creator.clone(), *board);
+            game.best_of = *best_of;
+            if let Some(time_control) = time_control {
+                game.clock = Clock::new(*time_control);
+            }
+            games.insert(game_id, game);
+            order.push(game_id);
+            continue;
+        }
+        let game = match games.get_mut(game_id) {
+            Some(game) => game,
+            None => continue,
+        };
+        match &record.event {
+            GameEvent::Created { .. } => {}
+            GameEvent::Seated {
+                name,
+                symbol,
+                resume_token,
+                bot,
+                registered,
+            } => {
+                let player = match bot {
+                    Some(difficulty) => Player::bot(*difficulty, symbol.clone()),
+                    None => {
+                        let mut player =
+                            Player::reserved(name.clone(), symbol.clone(), resume_token.clone());
+                        player.set_registered(*registered);
+                        player
+                    }
+                };
+                game.add_player(player);
+                if game.players.len() == 2 {
+                    game.set_status(GameStatus::InProgress);
+                }
+            }
+            GameEvent::Moved {
+                player,
+                symbol,
+                cell,
+            } => game.replay_move(MoveRecord {
+                player: player.clone(),
+                symbol: symbol.clone(),
+                cell: *cell,
+                at: record.at,
+            }),
+            GameEvent::Finished { .. } => {
+                game.set_status(GameStatus::Finished);
+                finished(game);
+            }
+            GameEvent::LostOnTime { symbol } => game.lose_on_time(symbol),
+            GameEvent::TookBack { symbol } => {
+                game.take_back(symbol);
+            }
+            GameEvent::Rated { .. } => {}
+            GameEvent::Restarted => game.rematch(),
+            GameEvent::Left { resume_token } => {
+                game.players
+                    .retain(|p| p.is_bot() || &p.get_resume_token() != resume_token);
+                game.reset_series();
+                game.reset();
+            }
+            GameEvent::Closed => {
+                games.remove(game_id);
+            }
+        }
+    }
+
+    order
+        .into_iter()
+        .filter_map(|game_id| games.remove(game_id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seated(name: &str, symbol: PlayerSymbol) -> GameEvent {
+        GameEvent::Seated {
+            name: name.to_string(),
+            symbol,
+            resume_token: format!("{}-token", name),
+            bot: None,
+            registered: true,
+        }
+    }
+
+    fn moved(player: &str, symbol: PlayerSymbol, cell: usize) -> GameEvent {
+        GameEvent::Moved {
+            player: player.to_string(),
+            symbol,
+            cell,
+        }
+    }
+
+    fn created() -> GameEvent {
+        GameEvent::Created {
+            creator: "alice".to_string(),
+            board: BoardConfig::default(),
+            best_of: None,
+            time_control: Some(TimeControl::per_move(30)),
+        }
+    }
+
+    // Writes the records to a fresh JSONL file and reads them back
+    fn round_trip(records: &[GameRecord]) -> Vec<GameRecord> {
+        let path = std::env::temp_dir().join(format!(
+            "tictactoe-store-{}-{}.jsonl",
+            std::process::id(),
+            records.len()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let store = FileStore::open(&path).unwrap();
+        for record in records {
+            store.append(record).unwrap();
+        }
+        let loaded = store.load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn unfinished_games_come_back_with_seats_held() {
+        let events = [
+            created(),
+            seated("alice", PlayerSymbol::X),
+            seated("bob", PlayerSymbol::O),
+            moved("alice", PlayerSymbol::X, 4),
+            moved("bob", PlayerSymbol::O, 0),
+        ];
+        let records: Vec<GameRecord> = events
+            .into_iter()
+            .map(|event| GameRecord::new("live", event))
+            .collect();
+
+        let games = restore(&round_trip(&records));
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.get_id(), "live");
+        assert_eq!(game.get_status(), GameStatus::WaitingForPlayers);
+        assert_eq!(game.board[4], Some(PlayerSymbol::X));
+        assert_eq!(game.board[0], Some(PlayerSymbol::O));
+        assert_eq!(game.clock.control, TimeControl::per_move(30));
+        let tokens: Vec<String> = game
+            .get_players()
+            .iter()
+            .map(|player| player.get_resume_token())
+            .collect();
+        assert_eq!(tokens, ["alice-token", "bob-token"]);
+        assert!(game
+            .get_players()
+            .iter()
+            .all(|player| !player.is_connected()));
+    }
+
+    #[test]
+    fn finished_and_closed_games_stay_closed_but_can_be_replayed() {
+        let mut events = vec![
+            created(),
+            seated("alice", PlayerSymbol::X),
+            seated("bob", PlayerSymbol::O),
+        ];
+        for (turn, cell) in [0, 3, 1, 4, 2].into_iter().enumerate() {
+            let (name, symbol) = match turn % 2 {
+                0 => ("alice", PlayerSymbol::X),
+                _ => ("bob", PlayerSymbol::O),
+            };
+            events.push(moved(name, symbol, cell));
+        }
+        events.push(GameEvent::Finished {
+            winner: Some(PlayerSymbol::X),
+        });
+        let mut records: Vec<GameRecord> = events
+            .into_iter()
+            .map(|event| GameRecord::new("done", event))
+            .collect();
+        records.push(GameRecord::new("gone", created()));
+        records.push(GameRecord::new("gone", GameEvent::Closed));
+
+        let loaded = round_trip(&records);
+        assert!(restore(&loaded).is_empty());
+        let done = replay(&loaded, "done").unwrap();
+        assert_eq!(done.moves.len(), 5);
+        assert_eq!(done.winner, Some(PlayerSymbol::X));
+        assert!(replay(&loaded, "gone").is_none());
+    }
+
+    #[test]
+    fn unreadable_lines_are_skipped() {
+        let path = std::env::temp_dir().join(format!(
+            "tictactoe-store-{}-broken.jsonl",
+            std::process::id()
+        ));
+        let record = serde_json::to_string(&GameRecord::new("g", created())).unwrap();
+        std::fs::write(&path, format!("{}\nnot json\n\n{}", record, &record[..10])).unwrap();
+        let loaded = FileStore::open(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+    }
+}
@@ -0,0 +1,26 @@
+// tls.rs
+
+use crate::config::TlsConfig;
+use native_tls::Identity;
+use std::fs;
+use std::path::Path;
+use tokio_native_tls::TlsAcceptor;
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))
+}
+
+/// Loads the certificate and key named in the config into an acceptor for wss:// connections.
+pub fn acceptor(tls: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let identity = match tls {
+        TlsConfig::Pem { cert, key } => Identity::from_pkcs8(&read(cert)?, &read(key)?)
+            .map_err(|e| format!("invalid PEM certificate or key: {}", e))?,
+        TlsConfig::Pkcs12 { bundle, password } => {
+            Identity::from_pkcs12(&read(bundle)?, password)
+                .map_err(|e| format!("invalid PKCS#12 bundle {}: {}", bundle.display(), e))?
+        }
+    };
+    native_tls::TlsAcceptor::new(identity)
+        .map(TlsAcceptor::from)
+        .map_err(|e| format!("can't set up TLS: {}", e))
+}
@@ -9,3 +9,61 @@ pub fn generate_id() -> String {
     let random_part: u16 = rng.gen_range(0..10000);
     format!("{}-{:04}", timestamp, random_part)
 }
+
+/// Unguessable token handed to a player so it can reclaim its seat after a dropped connection.
+pub fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..4)
+        .map(|_| format!("{:08x}", rng.gen::<u32>()))
+        .collect()
+}
+
+/// Whether `c` must not be passed on to other players' terminals: a control character,
+/// which includes line breaks and the escape that starts ANSI sequences, or an invisible one.
+pub fn is_unprintable(c: char) -> bool {
+    c.is_control() || is_invisible(c)
+}
+
+// Zero-width characters, bidirectional overrides and separators a terminal may break a
+// line on; they can disguise text or garble what is printed around it
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{061C}'
+            | '\u{115F}'
+            | '\u{1160}'
+            | '\u{17B4}'
+            | '\u{17B5}'
+            | '\u{180B}'..='\u{180F}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{2028}'..='\u{202E}'
+            | '\u{2060}'..='\u{206F}'
+            | '\u{3164}'
+            | '\u{FEFF}'
+            | '\u{FFA0}'
+            | '\u{FFF0}'..='\u{FFFB}'
+            | '\u{E0000}'..='\u{E0FFF}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printable_text_passes() {
+        assert!(!"Good game! ✨ Ça va? 你好".chars().any(is_unprintable));
+    }
+
+    #[test]
+    fn control_and_invisible_characters_are_caught() {
+        for c in ['\n', '\r', '\t', '\u{1b}', '\u{7f}'] {
+            assert!(is_unprintable(c), "{:?} passed", c);
+        }
+        for c in ['\u{200B}', '\u{202E}', '\u{2066}', '\u{FEFF}', '\u{E0041}'] {
+            assert!(is_unprintable(c), "{:?} passed", c);
+        }
+    }
+}
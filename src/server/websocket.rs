@@ -1,21 +1,41 @@
 // websocket.rs
-use crate::game::GameStatus;
-use crate::player::{Player, PlayerSymbol};
-use crate::server::GameServer;
+use crate::accounts::{Accounts, AuthError};
+use crate::actor::{GameCommand, GameHandle};
+use crate::board::BoardConfig;
+use crate::bot::Difficulty;
+use crate::clock::TimeControl;
+use crate::config::ServerConfig;
+use crate::game::{GameError, GameSettings};
+use crate::limits::{Inbound, Limit, TokenBucket};
+use crate::names;
+use crate::outbox::{Outbox, Outgoing};
+use crate::player::{Player, PlayerSymbol, Transport, WsStream};
+use crate::protocol::{
+    AuthMethod, ClientCommand, ErrorCode, GameMessage, Protocol, JSON_SUBPROTOCOL, PROTOCOL_VERSION,
+};
+use crate::rating::{DEFAULT_LEADERBOARD, MAX_LEADERBOARD};
+use crate::server::{GameServer, LobbyFilter};
+use crate::utils::{generate_id, generate_token};
+use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
 use log::{error, info, warn};
 use std::error::Error;
 use std::fmt;
 use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio::time::{timeout, Duration};
-use tokio_tungstenite::tungstenite::protocol::Message;
-use tokio_tungstenite::WebSocketStream;
-
-// Type aliases for convenience
-type WsStream = WebSocketStream<TcpStream>;
-type WsSink = Arc<Mutex<futures::stream::SplitSink<WsStream, Message>>>;
+use tokio::time::{timeout, Duration, Instant};
+use tokio_tungstenite::accept_hdr_async_with_config;
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message, WebSocketConfig};
+
+// How often a waiting connection re-checks whether it has become its turn
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+// Wrong passwords or signatures a connection may send before it is closed
+const MAX_SIGN_IN_ATTEMPTS: usize = 5;
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 #[derive(Debug)]
@@ -32,8 +52,11 @@ impl Error for MyCustomError {}
 // Enum to represent different game actions
 #[derive(Debug)]
 enum GameAction {
-    Move(usize),
+    Move(usize), // 0-based board index
+    Say(String),
     Restart,
+    Takeback,
+    AnswerTakeback(bool),
     Exit,
     Help,
     GetStatus,
@@ -45,422 +68,1389 @@ enum SessionState {
     Exit,
 }
 
-// Enum to represent different game messages
+// Whether a connection holds a seat in its game or only watches it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Player,
+    Spectator,
+}
+
+// Who a connection signed in as
 #[derive(Debug)]
-enum GameMessage {
-    Welcome,
-    ChooseOption,
-    GameCreated(String),
-    EnterGameId,
-    GameJoined(String),
-    InvalidChoice,
-    InvalidInput,
-    WaitingForPlayers,
-    GameOver,
-    CantRestart,
-    PlayerDisconnected(String),
-    GameRestarted,
-    Error(String),
-    InactiveDisconnect,
-    Help,
-    GameStatus(String),
-    // Custom(String),
+struct Identity {
+    name: String,
+    // Signed in to an account rather than playing as a guest
+    registered: bool,
+}
+
+// Frees the connection's name when it ends, however it ends
+struct SignedIn {
+    server: Arc<GameServer>,
+    name: String,
+    connection_id: String,
+}
+
+impl Drop for SignedIn {
+    fn drop(&mut self) {
+        self.server.sign_out(&self.name, &self.connection_id);
+    }
+}
+
+// What a connection sent to sign in with
+#[derive(Debug)]
+enum SignInRequest {
+    Guest(String),
+    Password {
+        name: String,
+        password: String,
+    },
+    Register {
+        name: String,
+        password: Option<String>,
+        public_key: Option<String>,
+    },
+    // Asks for a challenge to sign with the account's key
+    Key(String),
+    // Answers the challenge; JSON clients name the account again
+    Signature {
+        name: Option<String>,
+        signature: String,
+    },
+    Invalid,
+}
+
+// Choices offered by the main menu
+#[derive(Debug)]
+enum MenuChoice {
+    // Board, best-of length and time control, asked for when not given
+    Create(Option<GameSettings>),
+    Join(Option<String>),
+    QuickMatch,
+    PlayComputer(Option<Difficulty>),
+    Watch(Option<String>),
+    Resume(Option<String>),
+    // Filter plus `Some(true)` to subscribe or `Some(false)` to unsubscribe
+    Lobby(LobbyFilter, Option<bool>),
+    // Game ID, and whether to export the replay as JSON rather than step through it
+    Replay(Option<String>, bool),
+    // How many of the top players to list
+    Leaderboard(usize),
+    // A player's name, or `None` for the asking player's own
+    Stats(Option<String>),
+    Invalid,
 }
 
 // Struct to manage game session state
 struct GameSession {
-    ws_sink: WsSink,
-    server: Arc<Mutex<GameServer>>,
+    outbox: Outbox,
+    protocol: Protocol,
+    board: BoardConfig,
+    role: Role,
+    game: GameHandle,
+    server: Arc<GameServer>,
+    config: Arc<ServerConfig>,
     player_id: String,
-    game_id: String,
-    player_name: String,
+    connection_id: String,
 }
 
 impl GameSession {
-    fn new(
-        ws_sink: WsSink,
-        server: Arc<Mutex<GameServer>>,
-        player_id: String,
-        game_id: String,
-        player_name: String,
-    ) -> Self {
-        Self {
-            ws_sink,
-            server,
-            player_id,
-            game_id,
-            player_name,
-        }
-    }
-
-    async fn send_game_status(&self) -> Result<()> {
-        let server = self.server.lock().await;
-        if let Some(game) = server.get_game(&self.game_id) {
-            let mut game = game.lock().await;
-            let status = game.get_game_state();
-            self.send_message(GameMessage::GameStatus(status)).await?;
-        } else {
-            self.send_message(GameMessage::Error("Game not found".to_string()))
-                .await?;
+    // Passes a command to the game's task, telling the client if the game has closed
+    fn send_to_game(&self, command: GameCommand) -> Result<()> {
+        if self.game.send(command) {
+            return Ok(());
         }
-        Ok(())
+        self.send_message(GameMessage::Error(
+            ErrorCode::GameNotFound,
+            "Game not found".to_string(),
+        ))
+    }
+
+    fn send_game_status(&self) -> Result<()> {
+        self.send_to_game(GameCommand::Status {
+            participant_id: self.player_id.clone(),
+        })
     }
 
-    async fn handle_action(&self, action: GameAction) -> Result<SessionState> {
+    fn handle_action(&self, action: GameAction) -> Result<SessionState> {
+        if self.role == Role::Spectator {
+            return self.handle_spectator_action(action);
+        }
         match action {
-            GameAction::Move(position) => self.handle_move(position).await,
-            GameAction::Restart => self.handle_restart().await,
-            GameAction::Exit => self.handle_disconnect().await,
+            GameAction::Move(position) => {
+                self.send_to_game(GameCommand::Move {
+                    player_id: self.player_id.clone(),
+                    position,
+                })?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::Say(text) => {
+                self.send_to_game(GameCommand::Say {
+                    player_id: self.player_id.clone(),
+                    text,
+                })?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::Restart => {
+                self.send_to_game(GameCommand::Restart {
+                    player_id: self.player_id.clone(),
+                })?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::Takeback => {
+                self.send_to_game(GameCommand::Takeback {
+                    player_id: self.player_id.clone(),
+                })?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::AnswerTakeback(accept) => {
+                self.send_to_game(GameCommand::AnswerTakeback {
+                    player_id: self.player_id.clone(),
+                    accept,
+                })?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::Exit => Ok(self.handle_disconnect()),
             GameAction::Help => {
-                self.send_message(GameMessage::Help).await?;
+                self.send_message(GameMessage::Help)?;
                 Ok(SessionState::Continue)
             }
             GameAction::GetStatus => {
-                self.send_game_status().await?;
+                self.send_game_status()?;
                 Ok(SessionState::Continue)
             }
             GameAction::Invalid => {
-                self.send_message(GameMessage::InvalidInput).await?;
+                self.send_message(GameMessage::InvalidInput)?;
                 Ok(SessionState::Continue) // Keep session active
             }
         }
     }
 
-    async fn handle_move(&self, position: usize) -> Result<SessionState> {
-        let mut server = self.server.lock().await;
-        if let Some(game) = server.get_mut_game(&self.game_id) {
-            let mut game = game.lock().await;
-
-            match game.get_status() {
-                GameStatus::InProgress => match game.make_move(&self.player_id, position - 1) {
-                    Ok(state) => {
-                        game.broadcast_to_players(state).await;
-                        if game.get_status() == GameStatus::Finished {
-                            game.broadcast_to_players(GameMessage::GameOver.to_string())
-                                .await
-                        }
-                    }
-                    Err(e) => self.send_message(GameMessage::Error(e.to_string())).await?,
-                },
-                GameStatus::WaitingForPlayers => {
-                    self.send_message(GameMessage::WaitingForPlayers).await?
-                }
-                GameStatus::Finished => {
-                    game.broadcast_to_players(GameMessage::GameOver.to_string())
-                        .await
-                }
+    // Spectators are read-only: they may ask for the board or help, and `exit` only stops watching
+    fn handle_spectator_action(&self, action: GameAction) -> Result<SessionState> {
+        match action {
+            GameAction::Move(_)
+            | GameAction::Say(_)
+            | GameAction::Restart
+            | GameAction::Takeback
+            | GameAction::AnswerTakeback(_) => {
+                self.send_message(GameMessage::Error(
+                    ErrorCode::SpectatorReadOnly,
+                    "Spectators can't make moves, chat, take moves back or restart the game"
+                        .to_string(),
+                ))?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::Exit => Ok(self.handle_disconnect()),
+            GameAction::Help => {
+                self.send_message(GameMessage::SpectatorHelp)?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::GetStatus => {
+                self.send_game_status()?;
+                Ok(SessionState::Continue)
+            }
+            GameAction::Invalid => {
+                self.send_message(GameMessage::SpectatorHelp)?;
+                Ok(SessionState::Continue)
             }
         }
-        Ok(SessionState::Continue)
     }
 
-    async fn handle_restart(&self) -> Result<SessionState> {
-        let mut server = self.server.lock().await;
-        if let Some(game) = server.get_mut_game(&self.game_id) {
-            let mut game = game.lock().await;
-            if game.get_status() == GameStatus::Finished {
-                game.reset();
-                game.broadcast_to_players(GameMessage::GameRestarted.to_string())
-                    .await;
-                let status = game.get_game_state();
-                game.broadcast_to_players(status).await;
-            } else {
-                self.send_message(GameMessage::CantRestart).await?;
-            }
-        }
-        Ok(SessionState::Continue)
+    // Gives up the seat, or stops watching, at once
+    fn handle_disconnect(&self) -> SessionState {
+        self.game.send(GameCommand::Leave {
+            participant_id: self.player_id.clone(),
+        });
+        SessionState::Exit
     }
 
-    async fn handle_disconnect(&self) -> Result<SessionState> {
-        let mut server = self.server.lock().await;
-        if let Some(game) = server.get_mut_game(&self.game_id) {
-            let mut game = game.lock().await;
-            game.players.retain(|p| p.get_id() != self.player_id);
-            game.broadcast_to_players(
-                GameMessage::PlayerDisconnected(self.player_name.clone()).to_string(),
-            )
-            .await;
-            warn!("Player {} disconnected.", self.player_name);
-            game.reset();
-            if game.players.is_empty() {
-                drop(game);
-                server.remove_game(&self.game_id).await;
-            }
+    // A player's seat is held for them to resume; a spectator simply stops watching
+    fn handle_connection_lost(&self) -> SessionState {
+        if self.role == Role::Spectator {
+            return self.handle_disconnect();
         }
-        Ok(SessionState::Exit)
+        self.game.send(GameCommand::ConnectionLost {
+            player_id: self.player_id.clone(),
+            connection_id: self.connection_id.clone(),
+        });
+        SessionState::Exit
     }
 
-    async fn send_message(&self, message: GameMessage) -> Result<()> {
-        let message_text = message.to_string();
-        self.ws_sink
-            .lock()
-            .await
-            .send(Message::Text(message_text.into()))
-            .await
-            .map_err(|e| e.into())
+    fn send_message(&self, message: GameMessage) -> Result<()> {
+        send_message(&self.outbox, self.protocol, message)
     }
 
-    async fn is_game_in_progress(&self) -> bool {
-        let server = self.server.lock().await;
-        if let Some(game) = server.get_game(&self.game_id) {
-            let game = game.lock().await;
-            if game.get_status() == GameStatus::InProgress {
-                return true;
-            }
-        }
-        false
+    async fn is_my_turn(&self) -> bool {
+        let current = self
+            .game
+            .request(|reply| GameCommand::CurrentTurn { reply })
+            .await;
+        current == Some(Some(self.player_id.clone()))
     }
+}
 
-    async fn get_current_turn_player(&self) -> Option<String> {
-        let server = self.server.lock().await;
-        if let Some(game) = server.get_game(&self.game_id) {
-            let game = game.lock().await;
-            return game.get_current_turn_player();
+/// Completes the WebSocket handshake, selecting the JSON protocol when the client
+/// asks for the `tictactoe.v1.json` subprotocol.
+#[allow(clippy::result_large_err)] // the error type is fixed by tungstenite's handshake callback
+pub async fn accept_connection(
+    stream: Box<dyn Transport>,
+    config: &ServerConfig,
+) -> Result<(WsStream, Protocol)> {
+    let mut protocol = Protocol::Text;
+    let negotiate = |request: &Request, mut response: Response| {
+        let wants_json = request
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(',').any(|p| p.trim() == JSON_SUBPROTOCOL))
+            .unwrap_or(false);
+        if wants_json {
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(JSON_SUBPROTOCOL),
+            );
+            protocol = Protocol::Json;
         }
-        None
-    }
+        Ok(response)
+    };
+    // Larger messages fail to read, which closes the connection with 1009
+    let limits = WebSocketConfig::default()
+        .max_message_size(Some(config.max_message_size))
+        .max_frame_size(Some(config.max_message_size));
+    let ws_stream = accept_hdr_async_with_config(stream, negotiate, Some(limits)).await?;
+    Ok((ws_stream, protocol))
 }
 
-// Implementation for GameMessage to convert to string
-impl ToString for GameMessage {
-    fn to_string(&self) -> String {
-        match self {
-            GameMessage::Welcome => "🎉 Welcome to Tic-Tac-Toe! Please enter your name:".into(),
-            GameMessage::ChooseOption => "🕹️ Choose an option:\n1️⃣ Create a new game\n2️⃣ Join an existing game".into(),
-            GameMessage::GameCreated(id) => format!("✅ Game created! Your game ID is: {}\nWaiting for another player to join...", id),
-            GameMessage::EnterGameId => "🔍 Enter the game ID to join:".into(),
-            GameMessage::GameJoined(id) => format!("🎮 Joined game: {}", id),
-            GameMessage::InvalidChoice => "❌ Invalid choice, please restart.".into(),
-            GameMessage::InvalidInput => "❌ Invalid Input: Enter a number from 1 to 9".into(),
-            GameMessage::WaitingForPlayers => "⏳ Waiting for players to join the game...".into(),
-            GameMessage::GameOver => "🎉 Game over! Type `RESTART` to play again or `EXIT` to leave.".into(),
-            GameMessage::CantRestart => "❌ Error: You can't restart game before finishing current game ❗".into(),
-            GameMessage::PlayerDisconnected(name) => format!("❗ Player {} has left the game. ⏳ Waiting for a new player...", name),
-            GameMessage::GameRestarted => "🔄 Game restarted!".into(),
-            GameMessage::Error(e) => format!("❌ Error: {}", e),
-            GameMessage::InactiveDisconnect => "❗ Disconnected due to inactivity ⏰".into(),
-            GameMessage::Help => "🆘 Available Commands:\n- Enter 1-9 to make a move\n- Type 'restart' to restart the game\n- Type 'exit' to leave\n- Type 'status' to check the game status".into(),
-            GameMessage::GameStatus(status) => format!("📊 Game Status: {}", status),
+// Main client handler
+pub async fn handle_client(
+    ws_stream: WsStream,
+    protocol: Protocol,
+    server: Arc<GameServer>,
+    config: Arc<ServerConfig>,
+) {
+    let (ws_sink, ws_stream) = ws_stream.split();
+    let bucket = TokenBucket::new(config.message_rate, config.message_burst);
+    let mut ws_stream = Inbound::new(ws_stream, bucket);
+    let outbox = Outbox::new(config.outbox_size, config.slow_client_policy);
+    spawn_writer(ws_sink, outbox.clone(), server.clone());
 
-            // GameMessage::Custom(msg) => msg.clone(),
+    tokio::select! {
+        result = handle_connection(&mut ws_stream, &outbox, protocol, &server, config) => {
+            match result {
+                Ok(_) => info!("Client connection handled successfully"),
+                Err(e) => error!("Error handling client connection: {}", e),
+            }
         }
+        // The server is shutting down and its games have already been stopped
+        _ = server.closing() => {}
+    }
+    // Flush anything still queued, e.g. an inactivity notice, then close the socket,
+    // saying why when it wasn't the client's choice
+    if let Some(limit) = ws_stream.limit() {
+        warn!("Closing a connection: {}", limit.reason());
+        server.metrics().limit_hit(limit);
+        outbox.close_with(limit.close_code(), limit.reason());
+    } else if server.is_draining() {
+        outbox.close_with(CloseCode::Away, "The server is shutting down");
+    } else {
+        outbox.close();
     }
 }
 
-// Main client handler
-pub async fn handle_client(ws_stream: WsStream, server: Arc<Mutex<GameServer>>) {
-    let (ws_sink, mut ws_stream) = ws_stream.split();
-    let ws_sink = Arc::new(Mutex::new(ws_sink));
+// Owns the socket's write half and sends everything queued on the outbox, so game tasks
+// never wait on a slow client. A failed write marks the outbox gone, which the session
+// treats as a lost connection.
+fn spawn_writer(
+    mut ws_sink: SplitSink<WsStream, Message>,
+    outbox: Outbox,
+    server: Arc<GameServer>,
+) {
+    tokio::spawn(async move {
+        while let Some(Outgoing {
+            frame,
+            broadcast_at,
+        }) = outbox.next().await
+        {
+            let closing = matches!(frame, Message::Close(_));
+            let sent = tokio::select! {
+                sent = ws_sink.send(frame) => sent.is_ok(),
+                // Given up on while stuck writing to a client that stopped reading
+                _ = outbox.gone() => false,
+            };
+            if !sent || closing {
+                break;
+            }
+            if let Some(broadcast_at) = broadcast_at {
+                server
+                    .metrics()
+                    .broadcast_latency
+                    .observe(broadcast_at.elapsed().as_secs_f64());
+            }
+        }
+        outbox.finish();
+    });
+}
 
-    match handle_connection(&mut ws_stream, ws_sink, server).await {
-        Ok(_) => info!("Client connection handled successfully"),
-        Err(e) => error!("Error handling client connection: {}", e),
-    }
+/// Tells a client it went over a connection limit and closes the socket.
+pub async fn reject_client(mut ws_stream: WsStream, protocol: Protocol, limit: Limit) {
+    let message = GameMessage::Error(ErrorCode::ServerBusy, limit.reason().to_string());
+    let _ = ws_stream.send(protocol.encode(&message)).await;
+    let frame = CloseFrame {
+        code: limit.close_code(),
+        reason: limit.reason().into(),
+    };
+    let _ = ws_stream.close(Some(frame)).await;
 }
 
 async fn handle_connection(
-    ws_stream: &mut futures::stream::SplitStream<WsStream>,
-    ws_sink: WsSink,
-    server: Arc<Mutex<GameServer>>,
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    mut protocol: Protocol,
+    server: &Arc<GameServer>,
+    config: Arc<ServerConfig>,
 ) -> Result<()> {
+    // The name stays claimed until the connection ends
+    let (identity, _signed_in) = match sign_in(ws_stream, outbox, &mut protocol, server).await {
+        Ok(signed_in) => signed_in,
+        Err(_) => return Ok(()),
+    };
+
     // Initial setup
-    let (name, player_id, game_id) =
-        match setup_player(ws_stream, ws_sink.clone(), server.clone()).await {
-            Ok((name, player_id, game_id)) => (name, player_id, game_id),
+    let (player_id, connection_id, game_id, role) =
+        match setup_player(ws_stream, outbox, protocol, identity, server).await {
+            Ok(setup) => setup,
             Err(_) => return Ok(()),
         };
 
+    // The game may already have closed, e.g. if its only other player left
+    let game = match server.get_game(&game_id) {
+        Some(game) => game,
+        None => return Ok(()),
+    };
+
     // Create game session
-    let session = GameSession::new(
-        ws_sink.clone(),
-        server.clone(),
+    let session = GameSession {
+        outbox: outbox.clone(),
+        protocol,
+        board: game.config,
+        role,
+        game,
+        server: server.clone(),
+        config,
         player_id,
-        game_id,
-        name.clone(),
-    );
+        connection_id,
+    };
 
-    // Main game loop
-    handle_game_loop(ws_stream, &session).await
+    // Main game loop. If the connection fails mid-game the seat is still handed back.
+    let result = handle_game_loop(ws_stream, &session).await;
+    if result.is_err() {
+        session.handle_connection_lost();
+    }
+    result
 }
 
 async fn handle_game_setup(
-    ws_stream: &mut futures::stream::SplitStream<WsStream>,
-    ws_sink: WsSink,
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
     player: &mut Player,
-    server: Arc<Mutex<GameServer>>,
-) -> Result<String> {
-    send_message(&ws_sink, GameMessage::ChooseOption).await?;
+    server: &Arc<GameServer>,
+) -> Result<(String, Role)> {
+    loop {
+        send_message(outbox, protocol, GameMessage::ChooseOption)?;
 
-    match ws_stream.next().await {
-        Some(Ok(Message::Text(choice))) => match choice.trim() {
-            "1" => match create_new_game(ws_sink, player, server).await {
-                Ok(message) => Ok(message),
-                Err(e) => Err(e),
-            },
-            "2" => match join_existing_game(ws_stream, ws_sink, player, server).await {
-                Ok(message) => Ok(message),
-                Err(e) => Err(e),
-            },
-            _ => {
-                send_message(&ws_sink, GameMessage::InvalidChoice).await?;
-                Err("Invalid choice received from client".into())
+        let choice = match ws_stream.next().await {
+            Some(Ok(Message::Text(choice))) => parse_menu_choice(&choice, protocol),
+            _ => return Err("Invalid message format received".into()),
+        };
+        let seated = match choice {
+            MenuChoice::Create(settings) => {
+                create_new_game(ws_stream, outbox, protocol, settings, player, server)
+                    .await
+                    .map(|game_id| (game_id, Role::Player))
+            }
+            MenuChoice::Join(game_id) => {
+                join_existing_game(ws_stream, outbox, protocol, game_id, player, server)
+                    .await
+                    .map(|game_id| (game_id, Role::Player))
+            }
+            MenuChoice::QuickMatch => quick_match(outbox, protocol, player, server)
+                .await
+                .map(|game_id| (game_id, Role::Player)),
+            MenuChoice::PlayComputer(difficulty) => {
+                play_computer(ws_stream, outbox, protocol, difficulty, player, server)
+                    .await
+                    .map(|game_id| (game_id, Role::Player))
+            }
+            MenuChoice::Watch(game_id) => {
+                watch_game(ws_stream, outbox, protocol, game_id, player, server)
+                    .await
+                    .map(|game_id| (game_id, Role::Spectator))
+            }
+            MenuChoice::Lobby(filter, subscribe) => {
+                show_lobby(outbox, protocol, filter, subscribe, player, server)?;
+                continue;
+            }
+            MenuChoice::Replay(game_id, export) => {
+                replay_game(ws_stream, outbox, protocol, game_id, export, server).await?;
+                continue;
+            }
+            MenuChoice::Leaderboard(limit) => {
+                let players = server.leaderboard(limit);
+                send_message(outbox, protocol, GameMessage::Leaderboard(players))?;
+                continue;
+            }
+            MenuChoice::Stats(name) => {
+                let name = name.unwrap_or_else(|| player.get_name());
+                let message = match server.stats(&name) {
+                    Ok(stats) => GameMessage::Stats(stats),
+                    Err(e) => e.into(),
+                };
+                send_message(outbox, protocol, message)?;
+                continue;
+            }
+            MenuChoice::Resume(token) => {
+                match resume_game(ws_stream, outbox, protocol, token, player, server).await {
+                    // The seat already has a token, so there is nothing new to announce
+                    Ok(seated) => return Ok(seated),
+                    // An unknown or expired token has been reported, so offer the menu again
+                    Err(e) if e.is::<MyCustomError>() => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            MenuChoice::Invalid => {
+                send_message(outbox, protocol, GameMessage::InvalidChoice)?;
+                return Err("Invalid choice received from client".into());
+            }
+        };
+
+        // A failed create or join has already been reported to the client, so offer the menu again
+        match seated {
+            Ok((game_id, Role::Player)) => {
+                send_message(
+                    outbox,
+                    protocol,
+                    GameMessage::ResumeToken(player.get_resume_token()),
+                )?;
+                return Ok((game_id, Role::Player));
             }
+            Ok(seated) => return Ok(seated),
+            Err(e) if e.is::<MyCustomError>() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn parse_menu_choice(text: &str, protocol: Protocol) -> MenuChoice {
+    match protocol {
+        Protocol::Text => match text.trim() {
+            "1" => MenuChoice::Create(None),
+            "2" => MenuChoice::Join(None),
+            "3" => MenuChoice::QuickMatch,
+            "4" => MenuChoice::PlayComputer(None),
+            "5" => MenuChoice::Watch(None),
+            "6" => MenuChoice::Resume(None),
+            "7" => MenuChoice::Lobby(LobbyFilter::default(), None),
+            "8" => MenuChoice::Replay(None, false),
+            "9" => MenuChoice::Leaderboard(DEFAULT_LEADERBOARD),
+            text => {
+                let lower = text.to_lowercase();
+                if let Some(args) = lower.strip_prefix("lobby") {
+                    parse_lobby_args(args)
+                } else if let Some(args) = lower.strip_prefix("replay") {
+                    parse_replay_args(args)
+                } else if let Some(args) = lower.strip_prefix("leaderboard") {
+                    parse_leaderboard_args(args)
+                } else if lower == "stats" {
+                    MenuChoice::Stats(None)
+                } else if lower.starts_with("stats ") {
+                    // Names keep their case
+                    MenuChoice::Stats(Some(text["stats ".len()..].trim().to_string()))
+                } else {
+                    MenuChoice::Invalid
+                }
+            }
+        },
+        Protocol::Json => match ClientCommand::parse(text) {
+            Ok(ClientCommand::Create {
+                board,
+                best_of,
+                time_control,
+            }) => MenuChoice::Create(Some(GameSettings {
+                board: board.unwrap_or_default(),
+                best_of,
+                time_control,
+            })),
+            Ok(ClientCommand::Join { game_id }) => MenuChoice::Join(Some(game_id)),
+            Ok(ClientCommand::QuickMatch) => MenuChoice::QuickMatch,
+            Ok(ClientCommand::PlayComputer { difficulty }) => {
+                MenuChoice::PlayComputer(Some(difficulty))
+            }
+            Ok(ClientCommand::Watch { game_id }) => MenuChoice::Watch(Some(game_id)),
+            Ok(ClientCommand::Resume { token }) => MenuChoice::Resume(Some(token)),
+            Ok(ClientCommand::Replay { game_id }) => MenuChoice::Replay(Some(game_id), true),
+            Ok(ClientCommand::Leaderboard { limit }) => {
+                MenuChoice::Leaderboard(limit.unwrap_or(DEFAULT_LEADERBOARD).min(MAX_LEADERBOARD))
+            }
+            Ok(ClientCommand::Stats { name }) => MenuChoice::Stats(Some(name)),
+            Ok(ClientCommand::Lobby {
+                open,
+                board,
+                subscribe,
+            }) => MenuChoice::Lobby(
+                LobbyFilter {
+                    open_only: open,
+                    board,
+                },
+                subscribe,
+            ),
+            _ => MenuChoice::Invalid,
         },
-        _ => Err("Invalid message format received".into()),
     }
 }
 
+// Text lobby arguments, e.g. `open`, `15 5`, `subscribe` or `unsubscribe`, in any order
+fn parse_lobby_args(args: &str) -> MenuChoice {
+    let mut filter = LobbyFilter::default();
+    let mut subscribe = None;
+    let mut board = Vec::new();
+    for word in args.split_whitespace() {
+        match word {
+            "open" => filter.open_only = true,
+            "subscribe" => subscribe = Some(true),
+            "unsubscribe" => subscribe = Some(false),
+            word => board.push(word),
+        }
+    }
+    if !board.is_empty() {
+        match BoardConfig::parse(&board.join(" ")) {
+            Ok(config) => filter.board = Some(config),
+            Err(_) => return MenuChoice::Invalid,
+        }
+    }
+    MenuChoice::Lobby(filter, subscribe)
+}
+
+// Text replay arguments: `<game-id>`, optionally followed by `json`
+fn parse_replay_args(args: &str) -> MenuChoice {
+    match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [] => MenuChoice::Replay(None, false),
+        [game_id] => MenuChoice::Replay(Some(game_id.to_string()), false),
+        [game_id, "json"] => MenuChoice::Replay(Some(game_id.to_string()), true),
+        _ => MenuChoice::Invalid,
+    }
+}
+
+// Text leaderboard arguments: nothing, or how many players to list
+fn parse_leaderboard_args(args: &str) -> MenuChoice {
+    match args.trim() {
+        "" => MenuChoice::Leaderboard(DEFAULT_LEADERBOARD),
+        limit => match limit.parse::<usize>() {
+            Ok(limit) => MenuChoice::Leaderboard(limit.min(MAX_LEADERBOARD)),
+            Err(_) => MenuChoice::Invalid,
+        },
+    }
+}
+
+// Lists the lobby and updates the connection's subscription; the menu is shown again afterwards
+fn show_lobby(
+    outbox: &Outbox,
+    protocol: Protocol,
+    filter: LobbyFilter,
+    subscribe: Option<bool>,
+    player: &Player,
+    server: &Arc<GameServer>,
+) -> Result<()> {
+    match subscribe {
+        Some(true) => {
+            server.subscribe_lobby(&player.get_id(), outbox.clone(), protocol, filter);
+            send_message(outbox, protocol, GameMessage::LobbySubscribed(true))?;
+        }
+        Some(false) => {
+            server.unsubscribe_lobby(&player.get_id());
+            send_message(outbox, protocol, GameMessage::LobbySubscribed(false))?;
+        }
+        None => {}
+    }
+    let games = server.lobby(&filter);
+    send_message(outbox, protocol, GameMessage::Lobby(games))
+}
+
 async fn create_new_game(
-    ws_sink: WsSink,
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    settings: Option<GameSettings>,
     player: &Player,
-    server: Arc<Mutex<GameServer>>,
+    server: &Arc<GameServer>,
 ) -> Result<String> {
-    let mut server = server.lock().await;
-    let game_id = server.create_game(player.clone());
+    let settings = match settings {
+        Some(settings) => match settings.validate() {
+            Ok(settings) => settings,
+            Err(e) => {
+                send_message(
+                    outbox,
+                    protocol,
+                    GameMessage::Error(ErrorCode::InvalidBoard, e.clone()),
+                )?;
+                return Err(Box::new(MyCustomError(e)));
+            }
+        },
+        None => read_board_config(ws_stream, outbox, protocol).await?,
+    };
+    let game_id = match server.create_game(player.clone(), settings) {
+        Ok(game_id) => game_id,
+        Err(e) => {
+            let reason = e.to_string();
+            send_message(outbox, protocol, e.into())?;
+            return Err(Box::new(MyCustomError(reason)));
+        }
+    };
 
-    send_message(&ws_sink, GameMessage::GameCreated(game_id.clone())).await?;
+    send_message(
+        outbox,
+        protocol,
+        GameMessage::GameCreated(game_id.clone(), settings.board),
+    )?;
 
     info!("Player {} created game {}", player.get_name(), game_id);
     Ok(game_id)
 }
 
+// Board settings with an optional `boN` for a best-of-N match and a time control,
+// e.g. `15 5 bo3 5+3`
+fn parse_settings(text: &str) -> std::result::Result<GameSettings, String> {
+    let mut best_of = None;
+    let mut board = Vec::new();
+    let mut time = Vec::new();
+    for word in text.split_whitespace() {
+        match word.to_lowercase().strip_prefix("bo") {
+            Some(rounds) => match rounds.parse::<usize>() {
+                Ok(rounds) => best_of = Some(rounds),
+                Err(_) => return Err(format!("`{}` isn't a best-of length like `bo3`", word)),
+            },
+            None if TimeControl::is_time_word(word) => time.push(word),
+            None => board.push(word),
+        }
+    }
+    let time_control = match time.is_empty() {
+        true => None,
+        false => Some(TimeControl::parse(&time.join(" "))?),
+    };
+    GameSettings {
+        board: BoardConfig::parse(&board.join(" "))?,
+        best_of,
+        time_control,
+    }
+    .validate()
+}
+
+// Asks a text client for the board settings until it sends a valid answer
+async fn read_board_config(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+) -> Result<GameSettings> {
+    loop {
+        send_message(outbox, protocol, GameMessage::ChooseBoard)?;
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(text))) => match parse_settings(&text) {
+                Ok(settings) => return Ok(settings),
+                Err(e) => send_message(
+                    outbox,
+                    protocol,
+                    GameMessage::Error(ErrorCode::InvalidBoard, e),
+                )?,
+            },
+            _ => return Err("Invalid board settings received".into()),
+        }
+    }
+}
+
+async fn play_computer(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    difficulty: Option<Difficulty>,
+    player: &Player,
+    server: &Arc<GameServer>,
+) -> Result<String> {
+    let difficulty = match difficulty {
+        Some(difficulty) => difficulty,
+        None => {
+            send_message(outbox, protocol, GameMessage::ChooseDifficulty)?;
+            let difficulty = match ws_stream.next().await {
+                Some(Ok(Message::Text(text))) => Difficulty::parse(&text),
+                _ => None,
+            };
+            match difficulty {
+                Some(difficulty) => difficulty,
+                None => {
+                    send_message(outbox, protocol, GameMessage::InvalidChoice)?;
+                    return Err("Invalid difficulty received from client".into());
+                }
+            }
+        }
+    };
+    let game_id = match server.create_bot_game(player.clone(), difficulty) {
+        Ok(game_id) => game_id,
+        Err(e) => {
+            let reason = e.to_string();
+            send_message(outbox, protocol, e.into())?;
+            return Err(Box::new(MyCustomError(reason)));
+        }
+    };
+
+    send_message(outbox, protocol, GameMessage::GameJoined(game_id.clone()))?;
+
+    info!(
+        "Player {} started game {} against the {} computer",
+        player.get_name(),
+        game_id,
+        difficulty.label()
+    );
+    Ok(game_id)
+}
+
+async fn resume_game(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    token: Option<String>,
+    player: &mut Player,
+    server: &Arc<GameServer>,
+) -> Result<(String, Role)> {
+    let token = match token {
+        Some(token) => token.trim().to_string(),
+        None => {
+            send_message(outbox, protocol, GameMessage::EnterResumeToken)?;
+            match ws_stream.next().await {
+                Some(Ok(Message::Text(token))) => token.trim().to_string(),
+                _ => return Err("Invalid resume token received".into()),
+            }
+        }
+    };
+    match server
+        .resume_game(&token, &player.get_name(), outbox.clone(), protocol)
+        .await
+    {
+        Ok((game_id, seat)) => {
+            info!("Player {} resumed game {}", seat.get_name(), game_id);
+            *player = seat;
+            Ok((game_id, Role::Player))
+        }
+        Err(e) => {
+            let reason = e.to_string();
+            send_message(outbox, protocol, e.into())?;
+            Err(Box::new(MyCustomError(reason)))
+        }
+    }
+}
+
+async fn watch_game(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    game_id: Option<String>,
+    spectator: &Player,
+    server: &Arc<GameServer>,
+) -> Result<String> {
+    let game_id = match game_id {
+        Some(id) => id.trim().to_string(),
+        None => read_game_id(ws_stream, outbox, protocol, GameMessage::EnterGameId).await?,
+    };
+    match server.watch_game(&game_id, spectator.clone()).await {
+        Ok(()) => {
+            info!(
+                "Player {} is watching game {}",
+                spectator.get_name(),
+                game_id
+            );
+            Ok(game_id)
+        }
+        Err(e) => {
+            let reason = e.to_string();
+            send_message(outbox, protocol, e.into())?;
+            Err(Box::new(MyCustomError(reason)))
+        }
+    }
+}
+
+// Steps a text client through a game's last finished round, one move for each line it
+// sends; JSON clients and exports get the whole replay at once. The menu is shown again
+// afterwards.
+async fn replay_game(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    game_id: Option<String>,
+    export: bool,
+    server: &Arc<GameServer>,
+) -> Result<()> {
+    let game_id = match game_id {
+        Some(id) => id.trim().to_string(),
+        None => read_game_id(ws_stream, outbox, protocol, GameMessage::EnterReplayId).await?,
+    };
+    let replay = match server.replay(&game_id).await {
+        Ok(replay) => replay,
+        Err(e) => return send_message(outbox, protocol, e.into()),
+    };
+    if export || protocol == Protocol::Json {
+        return send_message(outbox, protocol, GameMessage::ReplayExport(replay));
+    }
+
+    send_message(outbox, protocol, GameMessage::Replay(replay.clone()))?;
+    for number in 1..=replay.moves.len() {
+        match ws_stream.next().await {
+            Some(Ok(Message::Text(text))) if text.trim().eq_ignore_ascii_case("stop") => {
+                return Ok(())
+            }
+            Some(Ok(Message::Text(_))) => {}
+            _ => return Err("Connection closed during a replay".into()),
+        }
+        if let Some(step) = replay.step(number) {
+            send_message(outbox, protocol, GameMessage::ReplayMove(step))?;
+        }
+    }
+    Ok(())
+}
+
+async fn read_game_id(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    prompt: GameMessage,
+) -> Result<String> {
+    send_message(outbox, protocol, prompt)?;
+
+    match ws_stream.next().await {
+        Some(Ok(Message::Text(id))) => Ok(id.trim().to_string()),
+        _ => {
+            send_message(
+                outbox,
+                protocol,
+                GameMessage::Error(
+                    ErrorCode::InvalidInput,
+                    "Invalid game ID received".to_string(),
+                ),
+            )?;
+            Err("Invalid game ID received".into())
+        }
+    }
+}
+
 async fn join_existing_game(
-    _ws_stream: &mut futures::stream::SplitStream<WsStream>,
-    ws_sink: WsSink,
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    game_id: Option<String>,
     player: &mut Player,
-    server: Arc<Mutex<GameServer>>,
+    server: &Arc<GameServer>,
 ) -> Result<String> {
-    send_message(&ws_sink, GameMessage::EnterGameId).await?;
-
-    // let game_id = match ws_stream.next().await {
-    //     Some(Ok(Message::Text(id))) => id,
-    //     _ => {
-    //         send_message(
-    //             &ws_sink,
-    //             GameMessage::Error("Invalid game ID recieved".to_string()),
-    //         )
-    //         .await?;
-    //         return Err("Invalid game ID received".into());
-    //     }
-    // };
+    let game_id = match game_id {
+        Some(id) => id.trim().to_string(),
+        None => read_game_id(ws_stream, outbox, protocol, GameMessage::EnterGameId).await?,
+    };
 
     player.set_symbol(PlayerSymbol::O);
-    let mut server = server.lock().await;
-    let game_id = match server.join_game(player.clone()).await {
-        Ok(id) => {
-            send_message(&ws_sink, GameMessage::GameJoined(id.to_string())).await?;
-            id
-        },
+    let result = server.join_game(&game_id, player.clone()).await;
+    report_join(outbox, protocol, player, server, result).await
+}
+
+async fn quick_match(
+    outbox: &Outbox,
+    protocol: Protocol,
+    player: &mut Player,
+    server: &Arc<GameServer>,
+) -> Result<String> {
+    player.set_symbol(PlayerSymbol::O);
+    let result = server.quick_match(player.clone()).await;
+    report_join(outbox, protocol, player, server, result).await
+}
+
+async fn report_join(
+    outbox: &Outbox,
+    protocol: Protocol,
+    player: &Player,
+    server: &GameServer,
+    result: std::result::Result<String, GameError>,
+) -> Result<String> {
+    match result {
+        Ok(game_id) => {
+            send_message(outbox, protocol, GameMessage::GameJoined(game_id.clone()))?;
+            info!("Player {} joined game {}", player.get_name(), game_id);
+            Ok(game_id)
+        }
         Err(e) => {
-            send_message(&ws_sink, GameMessage::Error(e.clone())).await?;
-            return Err(Box::new(MyCustomError(e)));
+            server.metrics().join_failed(e.code());
+            let reason = e.to_string();
+            send_message(outbox, protocol, e.into())?;
+            Err(Box::new(MyCustomError(reason)))
         }
-    };
-
-    info!("Player {} joined game {}", player.get_name(), game_id);
-    Ok(game_id.to_string())
+    }
 }
 
 async fn setup_player(
-    ws_stream: &mut futures::stream::SplitStream<WsStream>,
-    ws_sink: WsSink,
-    server: Arc<Mutex<GameServer>>,
-) -> Result<(String, String, String)> {
-    // Get player name
-    let name = get_player_name(ws_stream, ws_sink.clone()).await?;
-    info!("Player {} connected.", name);
-
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: Protocol,
+    identity: Identity,
+    server: &Arc<GameServer>,
+) -> Result<(String, String, String, Role)> {
     // Create player
-    let mut player = Player::new(name.clone(), PlayerSymbol::X, ws_sink.clone());
-    let player_id = player.get_id();
+    let mut player = Player::new(identity.name, PlayerSymbol::X, protocol, outbox.clone());
+    player.set_registered(identity.registered);
+    let menu_id = player.get_id();
 
     // Handle game setup
-    let game_id = match handle_game_setup(ws_stream, ws_sink, &mut player, server).await {
-        Ok(game_id) => game_id,
+    let setup = handle_game_setup(ws_stream, outbox, protocol, &mut player, server).await;
+    // Lobby updates are only sent while at the menu
+    server.unsubscribe_lobby(&menu_id);
+    let (game_id, role) = match setup {
+        Ok(joined) => joined,
         Err(err) => return Err(err),
     };
 
-    Ok((name, player_id, game_id))
+    // Resuming swaps in the seat the player held before, so read the identity afterwards
+    Ok((player.get_id(), player.get_connection_id(), game_id, role))
 }
 
-// Existing helper functions remain the same but use GameMessage enum
-async fn get_player_name(
-    ws_stream: &mut futures::stream::SplitStream<WsStream>,
-    ws_sink: WsSink,
-) -> Result<String> {
-    send_message(&ws_sink, GameMessage::Welcome).await?;
+// Signs the connection in, as a guest or to an account, and claims its name. A text
+// client may switch to the JSON protocol by sending a `hello` command first.
+async fn sign_in(
+    ws_stream: &mut Inbound,
+    outbox: &Outbox,
+    protocol: &mut Protocol,
+    server: &Arc<GameServer>,
+) -> Result<(Identity, SignedIn)> {
+    let allow_guests = server.config().allow_guests;
+    if *protocol == Protocol::Json {
+        send_message(outbox, *protocol, GameMessage::ProtocolAccepted)?;
+    }
+    send_message(outbox, *protocol, GameMessage::Welcome(allow_guests))?;
 
-    match ws_stream.next().await {
-        Some(Ok(Message::Text(name))) => Ok(name.to_string()),
-        _ => {
-            let _ = ws_sink.lock().await.send(Message::Close(None)).await;
-            Err("Failed to receive player name".into())
+    let connection_id = generate_id();
+    // Account name and the challenge sent for it
+    let mut challenge: Option<(String, String)> = None;
+    let mut failures = 0;
+    loop {
+        let text = match ws_stream.next().await {
+            Some(Ok(Message::Text(text))) => text,
+            _ => {
+                outbox.close();
+                return Err("Failed to receive player name".into());
+            }
+        };
+
+        let request = match (ClientCommand::parse(&text), *protocol) {
+            (Ok(ClientCommand::Hello { version, name }), _) => {
+                *protocol = Protocol::Json;
+                if version != PROTOCOL_VERSION {
+                    let message = format!(
+                        "Unsupported protocol version {}, server speaks {}",
+                        version, PROTOCOL_VERSION
+                    );
+                    send_message(
+                        outbox,
+                        *protocol,
+                        GameMessage::Error(ErrorCode::UnsupportedVersion, message.clone()),
+                    )?;
+                    outbox.close();
+                    return Err(message.into());
+                }
+                send_message(outbox, *protocol, GameMessage::ProtocolAccepted)?;
+                match name {
+                    Some(name) => SignInRequest::Guest(name),
+                    None => {
+                        send_message(outbox, *protocol, GameMessage::Welcome(allow_guests))?;
+                        continue;
+                    }
+                }
+            }
+            (Ok(ClientCommand::SetName { name }), Protocol::Json) => SignInRequest::Guest(name),
+            (
+                Ok(ClientCommand::Auth {
+                    method,
+                    name,
+                    password,
+                    public_key,
+                    signature,
+                }),
+                Protocol::Json,
+            ) => match (method, password, signature) {
+                (AuthMethod::Guest, _, _) => SignInRequest::Guest(name),
+                (AuthMethod::Password, Some(password), _) => {
+                    SignInRequest::Password { name, password }
+                }
+                (AuthMethod::Register, password, _) => SignInRequest::Register {
+                    name,
+                    password,
+                    public_key,
+                },
+                (AuthMethod::Key, _, Some(signature)) => SignInRequest::Signature {
+                    name: Some(name),
+                    signature,
+                },
+                (AuthMethod::Key, _, None) => SignInRequest::Key(name),
+                (AuthMethod::Password, None, _) => SignInRequest::Invalid,
+            },
+            (_, Protocol::Json) => {
+                send_message(
+                    outbox,
+                    *protocol,
+                    GameMessage::Error(
+                        ErrorCode::InvalidCommand,
+                        "Expected a `hello`, `set_name` or `auth` command".to_string(),
+                    ),
+                )?;
+                continue;
+            }
+            (_, Protocol::Text) => parse_sign_in(&text),
+        };
+
+        // Registering hashes a password like signing in does, so it uses up attempts too
+        let register = matches!(request, SignInRequest::Register { .. });
+        let identity = match request {
+            SignInRequest::Guest(name) => guest(&name, allow_guests, server),
+            SignInRequest::Password { name, password } => match names::normalize(&name) {
+                Ok(name) => check_credentials(server, move |accounts| {
+                    accounts.verify_password(&name, &password)
+                })
+                .await
+                .map(|name| Identity {
+                    name,
+                    registered: true,
+                }),
+                Err(e) => Err(e),
+            },
+            SignInRequest::Register {
+                name,
+                password,
+                public_key,
+            } => {
+                // Hold the name while the password is hashed, so nobody connects as it meanwhile
+                let claimed = server.names().check(&name).and_then(|name| {
+                    server.sign_in(&name, false, &connection_id, outbox, *protocol)?;
+                    Ok(name)
+                });
+                let registered = match claimed {
+                    Ok(claimed) => {
+                        let name = claimed.clone();
+                        let registered = check_credentials(server, move |accounts| {
+                            accounts.register(&name, password.as_deref(), public_key.as_deref())
+                        })
+                        .await;
+                        if registered.is_err() {
+                            server.sign_out(&claimed, &connection_id);
+                        }
+                        registered
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Ok(account) = &registered {
+                    info!("Account {} created", account.name);
+                    send_message(
+                        outbox,
+                        *protocol,
+                        GameMessage::AccountCreated(account.name.clone()),
+                    )?;
+                }
+                registered.map(|account| Identity {
+                    name: account.name,
+                    registered: true,
+                })
+            }
+            // Every name gets a challenge, so asking for one doesn't tell whether the name
+            // has an account; signing it only works for one with a key
+            SignInRequest::Key(name) => match names::normalize(&name) {
+                Ok(name) => {
+                    let nonce = generate_token();
+                    send_message(outbox, *protocol, GameMessage::AuthChallenge(nonce.clone()))?;
+                    challenge = Some((name, nonce));
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+            SignInRequest::Signature { name, signature } => match challenge.take() {
+                Some((account, nonce))
+                    if name.is_none_or(|name| name.trim().eq_ignore_ascii_case(&account)) =>
+                {
+                    server
+                        .accounts()
+                        .verify_signature(&account, &nonce, &signature)
+                        .map(|name| Identity {
+                            name,
+                            registered: true,
+                        })
+                }
+                _ => Err(AuthError::NoChallenge),
+            },
+            SignInRequest::Invalid => {
+                send_message(
+                    outbox,
+                    *protocol,
+                    GameMessage::Error(
+                        ErrorCode::InvalidCommand,
+                        "Sign in with `login <name> <password>`, `register <name> <password>`, `register <name> key <public-key>` or `key <name>`".to_string(),
+                    ),
+                )?;
+                continue;
+            }
+        };
+
+        let signed_in = identity.and_then(|identity| {
+            server.sign_in(
+                &identity.name,
+                identity.registered,
+                &connection_id,
+                outbox,
+                *protocol,
+            )?;
+            Ok(identity)
+        });
+        match signed_in {
+            Ok(identity) => {
+                info!(
+                    "Player {} connected{}",
+                    identity.name,
+                    if identity.registered {
+                        ""
+                    } else {
+                        " as a guest"
+                    }
+                );
+                send_message(
+                    outbox,
+                    *protocol,
+                    GameMessage::SignedIn(identity.name.clone(), identity.registered),
+                )?;
+                let signed_in = SignedIn {
+                    server: server.clone(),
+                    name: identity.name.clone(),
+                    connection_id,
+                };
+                return Ok((identity, signed_in));
+            }
+            Err(e) => {
+                let wrong = matches!(e, AuthError::WrongPassword | AuthError::WrongSignature);
+                let rename = matches!(
+                    e.code(),
+                    ErrorCode::InvalidName | ErrorCode::NameTaken | ErrorCode::NameInUse
+                );
+                send_message(outbox, *protocol, e.into())?;
+                if rename {
+                    send_message(outbox, *protocol, GameMessage::ChooseAnotherName)?;
+                }
+                if wrong || register {
+                    failures += 1;
+                }
+                if failures >= MAX_SIGN_IN_ATTEMPTS {
+                    send_message(outbox, *protocol, AuthError::TooManyAttempts.into())?;
+                    outbox.close();
+                    return Err("Too many failed sign-in attempts".into());
+                }
+            }
         }
     }
 }
 
-async fn handle_game_loop(
-    ws_stream: &mut futures::stream::SplitStream<WsStream>,
-    session: &GameSession,
-) -> Result<()> {
-    'game_loop: while let Some(message) = {
-        if session.is_game_in_progress().await
-            && session.get_current_turn_player().await == Some(session.player_id.clone())
-        {
-            match timeout(Duration::from_secs(30), ws_stream.next()).await {
-                Ok(Some(msg_result)) => Some(msg_result),
-                Ok(None) => continue 'game_loop,
-                Err(_) => {
-                    session
-                        .send_message(GameMessage::InactiveDisconnect)
-                        .await?;
-                    session.handle_disconnect().await?;
-                    return Ok(());
+// `login <name> <password>`, `register <name> <password>`, `register <name> key
+// <public-key>`, `key <name>`, `signature <signature>` and `guest <name>`; any other
+// text is a guest's name
+fn parse_sign_in(text: &str) -> SignInRequest {
+    let trimmed = text.trim();
+    let (command, rest) = trimmed
+        .split_once(char::is_whitespace)
+        .unwrap_or((trimmed, ""));
+    let rest = rest.trim();
+    let (name, secret) = rest
+        .split_once(char::is_whitespace)
+        .map_or((rest, ""), |(name, secret)| (name, secret.trim()));
+    let (name, secret) = (name.to_string(), secret.to_string());
+
+    match command.to_lowercase().as_str() {
+        "login" | "register" | "key" | "signature" | "guest" if rest.is_empty() => {
+            SignInRequest::Invalid
+        }
+        "login" | "register" if secret.is_empty() => SignInRequest::Invalid,
+        "login" => SignInRequest::Password {
+            name,
+            password: secret,
+        },
+        "register" => match secret.split_once(char::is_whitespace) {
+            Some((word, public_key)) if word.eq_ignore_ascii_case("key") => {
+                SignInRequest::Register {
+                    name,
+                    password: None,
+                    public_key: Some(public_key.trim().to_string()),
                 }
             }
+            _ => SignInRequest::Register {
+                name,
+                password: Some(secret),
+                public_key: None,
+            },
+        },
+        "key" if secret.is_empty() => SignInRequest::Key(name),
+        "key" => SignInRequest::Invalid,
+        "signature" => SignInRequest::Signature {
+            name: None,
+            signature: rest.to_string(),
+        },
+        "guest" => SignInRequest::Guest(rest.to_string()),
+        _ => SignInRequest::Guest(trimmed.to_string()),
+    }
+}
+
+// Guests may use any name the name policy allows and no account has
+fn guest(
+    name: &str,
+    allow_guests: bool,
+    server: &GameServer,
+) -> std::result::Result<Identity, AuthError> {
+    if !allow_guests {
+        return Err(AuthError::GuestsDisabled);
+    }
+    let name = server.names().check(name)?;
+    if server.accounts().is_registered(&name) {
+        return Err(AuthError::Unavailable);
+    }
+    Ok(Identity {
+        name,
+        registered: false,
+    })
+}
+
+// Password hashing is deliberately slow, so it runs off the async runtime
+async fn check_credentials<T: Send + 'static>(
+    server: &Arc<GameServer>,
+    check: impl FnOnce(&Accounts) -> std::result::Result<T, AuthError> + Send + 'static,
+) -> std::result::Result<T, AuthError> {
+    let server = server.clone();
+    tokio::task::spawn_blocking(move || check(server.accounts()))
+        .await
+        .unwrap_or(Err(AuthError::Storage))
+}
+
+async fn handle_game_loop(ws_stream: &mut Inbound, session: &GameSession) -> Result<()> {
+    // A game's clock already limits each turn, so only untimed games watch for players
+    // who stop answering. Spectators may watch silently for as long as they like.
+    let watch_idle = session.role == Role::Player && !session.game.time_control.is_timed();
+    let mut last_activity = Instant::now();
+    'game_loop: while let Some(message) = {
+        // Idle time only counts on the player's own turn, not while they wait for an
+        // opponent to join or to move
+        if !watch_idle || !session.is_my_turn().await {
+            last_activity = Instant::now();
+        }
+        let idle = last_activity.elapsed();
+        if watch_idle && idle >= session.config.idle_timeout {
+            session.server.metrics().inactivity_disconnects.inc();
+            session.send_message(GameMessage::InactiveDisconnect)?;
+            // Held like a dropped connection, so the player can still resume
+            session.handle_connection_lost();
+            return Ok(());
+        }
+        let wait = if watch_idle {
+            POLL_INTERVAL.min(session.config.idle_timeout.saturating_sub(idle))
         } else {
-            match timeout(Duration::from_secs(10), ws_stream.next()).await {
-                Ok(Some(msg_result)) => Some(msg_result),
-                _ => continue 'game_loop,
-            }
+            POLL_INTERVAL
+        };
+        match timeout(wait, next_message(ws_stream, session)).await {
+            Ok(next) => next,
+            Err(_) => continue 'game_loop,
         }
     } {
         match message {
             Ok(Message::Text(text)) => {
-                let action = parse_game_action(&text);
-                match session.handle_action(action).await {
-                    Ok(SessionState::Exit) => break,
+                last_activity = Instant::now();
+                let action = parse_game_action(&text, session.protocol, &session.board);
+                match session.handle_action(action) {
+                    Ok(SessionState::Exit) => return Ok(()),
                     _ => continue,
                 }
             }
             Ok(Message::Close(_)) | Err(_) => {
-                session.handle_disconnect().await?;
+                session.handle_connection_lost();
                 return Ok(());
             }
             _ => continue,
         }
     }
+    // The stream ended without a close frame
+    session.handle_connection_lost();
     Ok(())
 }
 
-fn parse_game_action(text: &str) -> GameAction {
+// Waits for the client's next message. A connection whose writer has given up on it,
+// because the socket failed or the client fell too far behind, reads as ended, as does
+// one whose game has closed under it.
+async fn next_message(
+    ws_stream: &mut Inbound,
+    session: &GameSession,
+) -> Option<std::result::Result<Message, tungstenite::Error>> {
+    tokio::select! {
+        message = ws_stream.next() => message,
+        _ = session.outbox.gone() => None,
+        _ = session.game.closed() => None,
+    }
+}
+
+fn parse_game_action(text: &str, protocol: Protocol, board: &BoardConfig) -> GameAction {
+    if protocol == Protocol::Json {
+        // JSON clients address cells by their 0-based index in the board snapshot,
+        // or by 0-based row and column
+        return match ClientCommand::parse(text) {
+            Ok(ClientCommand::Move {
+                cell: Some(cell), ..
+            }) => GameAction::Move(cell),
+            Ok(ClientCommand::Move {
+                row: Some(row),
+                col: Some(col),
+                ..
+            }) => match board.index(row, col) {
+                Some(cell) => GameAction::Move(cell),
+                None => GameAction::Invalid,
+            },
+            Ok(ClientCommand::Say { text }) => GameAction::Say(text),
+            Ok(ClientCommand::Restart) => GameAction::Restart,
+            Ok(ClientCommand::Takeback) => GameAction::Takeback,
+            Ok(ClientCommand::TakebackReply { accept }) => GameAction::AnswerTakeback(accept),
+            Ok(ClientCommand::Exit) => GameAction::Exit,
+            Ok(ClientCommand::Help) => GameAction::Help,
+            Ok(ClientCommand::Status) => GameAction::GetStatus,
+            _ => GameAction::Invalid,
+        };
+    }
+
+    // Chat keeps the message's original case
+    let trimmed = text.trim();
+    let (command, message) = trimmed
+        .split_once(char::is_whitespace)
+        .unwrap_or((trimmed, ""));
+    if command.eq_ignore_ascii_case("say") {
+        return GameAction::Say(message.to_string());
+    }
+
     match text.trim().to_lowercase().as_str() {
         "exit" => GameAction::Exit,
         "restart" => GameAction::Restart,
+        "takeback" => GameAction::Takeback,
+        "accept" => GameAction::AnswerTakeback(true),
+        "decline" => GameAction::AnswerTakeback(false),
         "help" => GameAction::Help,
         "status" => GameAction::GetStatus,
-        text => match text.parse::<usize>() {
-            Ok(position) if (1..=9).contains(&position) => GameAction::Move(position),
-            _ => GameAction::Invalid,
+        text => match board.parse_cell(text) {
+            Some(position) => GameAction::Move(position),
+            None => GameAction::Invalid,
         },
     }
 }
 
-async fn send_message(ws_sink: &WsSink, message: GameMessage) -> Result<()> {
-    ws_sink
-        .lock()
-        .await
-        .send(Message::Text(message.to_string().into()))
-        .await
-        .map_err(|e| e.into())
+fn send_message(outbox: &Outbox, protocol: Protocol, message: GameMessage) -> Result<()> {
+    if outbox.send(protocol.encode(&message), &message) {
+        Ok(())
+    } else {
+        Err("Connection closed".into())
+    }
 }